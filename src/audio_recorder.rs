@@ -1,4 +1,5 @@
 use super::*;
+use std::collections::VecDeque;
 
 use ffmpeg_next::codec::encoder;
 use ffmpeg_next::codec::encoder::audio::Audio;
@@ -8,8 +9,12 @@ use ffmpeg_next::util::channel_layout::ChannelLayout;
 use ffmpeg_next::util::format::sample as ffmpeg_sample;
 use ffmpeg_next::util::frame::Audio as AudioFrame;
 
+use crate::filter::AudioFilter;
 use crate::worker::WorkerControlMessage;
 
+/// Raw audio waiting for the encoder thread, 10ms per frame
+const AUDIO_QUEUE_CAPACITY: usize = 500;
+
 pub(super) enum AudioRecorderMessage {
     Quit,
     RawAudioPacket(i64, ffmpeg_next::util::frame::Audio),
@@ -23,8 +28,480 @@ impl From<worker::WorkerControlMessage> for AudioRecorderMessage {
     }
 }
 
+/// Silences the audio while it is quieter than a threshold, e.g. to hide background hiss
+/// between sentences
+#[derive(Clone, Debug)]
+pub struct NoiseGateConfig {
+    /// Level in dBFS below which the gate closes
+    pub threshold_db: f32,
+    /// Time for the gate to open once the level rises above the threshold
+    pub attack: std::time::Duration,
+    /// Time for the gate to close once the level falls below the threshold
+    pub release: std::time::Duration,
+}
+
+impl Default for NoiseGateConfig {
+    fn default() -> Self {
+        NoiseGateConfig {
+            threshold_db: -45.0,
+            attack: std::time::Duration::from_millis(5),
+            release: std::time::Duration::from_millis(150),
+        }
+    }
+}
+
+/// How quickly the level the gate compares against falls after a peak
+const NOISE_GATE_ENVELOPE_RELEASE: std::time::Duration = std::time::Duration::from_millis(50);
+
+fn db_to_gain(db: f64) -> f64 {
+    10f64.powf(db / 20.0)
+}
+
+/// Coefficient of a one pole smoother that covers most of the distance in `time`
+fn smoothing_coefficient(time: std::time::Duration, rate: f64) -> f32 {
+    let samples = time.as_secs_f64() * rate;
+    if samples < 1.0 {
+        1.0
+    } else {
+        (1.0 - (-1.0 / samples).exp()) as f32
+    }
+}
+
+struct NoiseGate {
+    threshold: f32,
+    attack: f32,
+    release: f32,
+    envelope_decay: f32,
+    envelope: f32,
+    gain: f32,
+}
+
+impl NoiseGate {
+    fn new(config: &NoiseGateConfig, sample_rate: u32) -> Self {
+        let rate = sample_rate as f64;
+        Self {
+            threshold: db_to_gain(config.threshold_db as f64) as f32,
+            attack: smoothing_coefficient(config.attack, rate),
+            release: smoothing_coefficient(config.release, rate),
+            envelope_decay: 1.0 - smoothing_coefficient(NOISE_GATE_ENVELOPE_RELEASE, rate),
+            envelope: 0.0,
+            gain: 0.0,
+        }
+    }
+
+    fn process(&mut self, samples: &mut [f32], channels: usize) {
+        for frame in samples.chunks_exact_mut(channels) {
+            let level = frame
+                .iter()
+                .fold(0f32, |level, sample| level.max(sample.abs()));
+            self.envelope = level.max(self.envelope * self.envelope_decay);
+            let (target, coefficient) = if self.envelope >= self.threshold {
+                (1.0, self.attack)
+            } else {
+                (0.0, self.release)
+            };
+            self.gain += (target - self.gain) * coefficient;
+            for sample in frame.iter_mut() {
+                *sample *= self.gain;
+            }
+        }
+    }
+}
+
+/// A second order IIR filter in transposed direct form II
+#[derive(Clone)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    #[inline]
+    fn process(&mut self, input: f64) -> f64 {
+        let output = self.b[0] * input + self.z[0];
+        self.z[0] = self.b[1] * input - self.a[0] * output + self.z[1];
+        self.z[1] = self.b[2] * input - self.a[1] * output;
+        output
+    }
+}
+
+/// The two stage K-weighting filter of ITU-R BS.1770, a high shelf followed by a high pass,
+/// with the coefficients derived for any sample rate
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let rate = sample_rate as f64;
+
+    let (frequency, gain_db, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (std::f64::consts::PI * frequency / rate).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    let (frequency, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (std::f64::consts::PI * frequency / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    [shelf, high_pass]
+}
+
+const LOUDNESS_ABSOLUTE_GATE: f64 = -70.0;
+const LOUDNESS_RELATIVE_GATE: f64 = -10.0;
+/// Gating blocks are binned by loudness in steps of 0.1 LU up to +10 LUFS, so the
+/// integrated loudness of a recording of any length is computed in constant time and memory
+const LOUDNESS_HISTOGRAM_BINS: usize = 800;
+
+fn energy_to_loudness(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+/// Measures the integrated loudness of EBU R128, gated 400ms blocks every 100ms
+struct LoudnessMeter {
+    filters: Vec<[Biquad; 2]>,
+    sub_block_length: usize,
+    sub_block_position: usize,
+    sub_block_energy: f64,
+    /// Mean square of the last four 100ms sub-blocks, summed over the channels
+    sub_blocks: VecDeque<f64>,
+    /// Number of blocks and the sum of their energies for every loudness bin
+    histogram: Vec<(u64, f64)>,
+    integrated: Option<f64>,
+}
+
+impl LoudnessMeter {
+    fn new(sample_rate: u32, channels: usize) -> Self {
+        Self {
+            filters: vec![k_weighting(sample_rate); channels],
+            sub_block_length: (sample_rate / 10) as usize,
+            sub_block_position: 0,
+            sub_block_energy: 0.0,
+            sub_blocks: VecDeque::with_capacity(4),
+            histogram: vec![(0, 0.0); LOUDNESS_HISTOGRAM_BINS],
+            integrated: None,
+        }
+    }
+
+    /// Integrated loudness in LUFS, None until a block louder than the absolute gate
+    fn integrated_loudness(&self) -> Option<f64> {
+        self.integrated
+    }
+
+    /// Add interleaved samples, returns true if the integrated loudness was updated
+    fn add(&mut self, samples: &[f32]) -> bool {
+        let mut updated = false;
+        for frame in samples.chunks_exact(self.filters.len()) {
+            for (sample, [shelf, high_pass]) in frame.iter().zip(self.filters.iter_mut()) {
+                let weighted = high_pass.process(shelf.process(*sample as f64));
+                self.sub_block_energy += weighted * weighted;
+            }
+            self.sub_block_position += 1;
+            if self.sub_block_position < self.sub_block_length {
+                continue;
+            }
+
+            if self.sub_blocks.len() == 4 {
+                self.sub_blocks.pop_front();
+            }
+            self.sub_blocks
+                .push_back(self.sub_block_energy / self.sub_block_length as f64);
+            self.sub_block_position = 0;
+            self.sub_block_energy = 0.0;
+            if self.sub_blocks.len() == 4 {
+                let energy = self.sub_blocks.iter().sum::<f64>() / 4.0;
+                updated |= self.add_block(energy);
+            }
+        }
+        updated
+    }
+
+    fn add_block(&mut self, energy: f64) -> bool {
+        let loudness = energy_to_loudness(energy);
+        if loudness <= LOUDNESS_ABSOLUTE_GATE {
+            return false;
+        }
+        let bin = ((loudness - LOUDNESS_ABSOLUTE_GATE) * 10.0) as usize;
+        let bin = &mut self.histogram[bin.min(LOUDNESS_HISTOGRAM_BINS - 1)];
+        bin.0 += 1;
+        bin.1 += energy;
+
+        let (count, total) = self.histogram.iter().fold((0, 0.0), |(count, total), bin| {
+            (count + bin.0, total + bin.1)
+        });
+        let relative_gate = energy_to_loudness(total / count as f64) + LOUDNESS_RELATIVE_GATE;
+        let first_bin = ((relative_gate - LOUDNESS_ABSOLUTE_GATE) * 10.0)
+            .ceil()
+            .max(0.0) as usize;
+        let (count, total) = self
+            .histogram
+            .iter()
+            .skip(first_bin)
+            .fold((0, 0.0), |(count, total), bin| {
+                (count + bin.0, total + bin.1)
+            });
+        if count > 0 {
+            self.integrated = Some(energy_to_loudness(total / count as f64));
+        }
+        true
+    }
+}
+
+/// Highest sample peak the loudness normalization lets through, in dBFS
+const LOUDNESS_PEAK_CEILING_DB: f64 = -1.0;
+/// Largest correction of the loudness normalization, so near silence is not blown up
+const LOUDNESS_MAX_GAIN_DB: f64 = 20.0;
+/// Time for the peak limiter to recover after it reduced the gain
+const LOUDNESS_LIMITER_RELEASE: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Moves the integrated loudness towards a target as it is measured, with a peak limiter so
+/// the added gain does not clip
+struct LoudnessNormalizer {
+    target: f64,
+    input_meter: LoudnessMeter,
+    ceiling: f32,
+    limiter_release: f32,
+    limiter_gain: f32,
+    /// Gain applied at the end of the previous frame
+    gain: f32,
+}
+
+impl LoudnessNormalizer {
+    fn new(target: f64, sample_rate: u32, channels: usize) -> Self {
+        Self {
+            target,
+            input_meter: LoudnessMeter::new(sample_rate, channels),
+            ceiling: db_to_gain(LOUDNESS_PEAK_CEILING_DB) as f32,
+            limiter_release: smoothing_coefficient(LOUDNESS_LIMITER_RELEASE, sample_rate as f64),
+            limiter_gain: 1.0,
+            gain: 1.0,
+        }
+    }
+
+    fn process(&mut self, samples: &mut [f32], channels: usize) {
+        self.input_meter.add(samples);
+        let loudness_gain = match self.input_meter.integrated_loudness() {
+            Some(loudness) => db_to_gain(
+                (self.target - loudness).clamp(-LOUDNESS_MAX_GAIN_DB, LOUDNESS_MAX_GAIN_DB),
+            ) as f32,
+            None => 1.0,
+        };
+
+        let frames = samples.len() / channels;
+        if frames == 0 {
+            return;
+        }
+        // The limiter recovers per sample, so follow it over the length of this frame
+        let recovered =
+            1.0 - (1.0 - self.limiter_gain) * (1.0 - self.limiter_release).powi(frames as i32);
+        let peak = samples
+            .iter()
+            .fold(0f32, |peak, sample| peak.max(sample.abs()));
+        let limit = if peak * loudness_gain > self.ceiling {
+            self.ceiling / (peak * loudness_gain)
+        } else {
+            1.0
+        };
+        self.limiter_gain = recovered.min(limit);
+
+        // Gain only ramps up, reductions apply to the whole frame so its peaks stay under
+        // the ceiling
+        let gain = loudness_gain * self.limiter_gain;
+        let start_gain = gain.min(self.gain);
+        for (index, frame) in samples.chunks_exact_mut(channels).enumerate() {
+            let frame_gain = start_gain + (gain - start_gain) * (index + 1) as f32 / frames as f32;
+            for sample in frame.iter_mut() {
+                *sample *= frame_gain;
+            }
+        }
+        self.gain = gain;
+    }
+}
+
+/// The built-in processing of the audio right before it is encoded: the noise gate, loudness
+/// normalization and measuring the loudness of the recording
+struct AudioProcessor {
+    channels: usize,
+    noise_gate: Option<NoiseGate>,
+    normalizer: Option<LoudnessNormalizer>,
+    meter: LoudnessMeter,
+    samples: Vec<f32>,
+    stats: std::sync::Arc<StatsCounters>,
+}
+
+impl AudioProcessor {
+    fn new(
+        config: &SlickscreenConfig,
+        sample_rate: u32,
+        channels: usize,
+        stats: std::sync::Arc<StatsCounters>,
+    ) -> Self {
+        Self {
+            channels,
+            noise_gate: config
+                .noise_gate
+                .as_ref()
+                .map(|noise_gate| NoiseGate::new(noise_gate, sample_rate)),
+            normalizer: config
+                .loudness_target
+                .map(|target| LoudnessNormalizer::new(target, sample_rate, channels)),
+            meter: LoudnessMeter::new(sample_rate, channels),
+            samples: Vec::new(),
+            stats,
+        }
+    }
+
+    /// Process a frame of interleaved 16 bit samples in place
+    fn process(&mut self, frame: &mut AudioFrame) {
+        let modifies = self.noise_gate.is_some() || self.normalizer.is_some();
+        // Frames coming out of a filter graph may share their buffers
+        if modifies && unsafe { ffmpeg_next::ffi::av_frame_make_writable(frame.as_mut_ptr()) } < 0 {
+            println!("Unable to process audio frame, its buffer is not writable");
+            return;
+        }
+
+        let length = frame.samples() * self.channels * 2;
+        let data = &mut frame.data_mut(0)[..length];
+        self.samples.clear();
+        self.samples.extend(
+            data.chunks_exact(2)
+                .map(|bytes| i16::from_ne_bytes([bytes[0], bytes[1]]) as f32 / 32768.0),
+        );
+
+        if let Some(noise_gate) = self.noise_gate.as_mut() {
+            noise_gate.process(&mut self.samples, self.channels);
+        }
+        if let Some(normalizer) = self.normalizer.as_mut() {
+            normalizer.process(&mut self.samples, self.channels);
+        }
+        if self.meter.add(&self.samples) {
+            *self.stats.audio_integrated_loudness.lock().unwrap() =
+                self.meter.integrated_loudness();
+        }
+
+        if modifies {
+            for (bytes, sample) in data.chunks_exact_mut(2).zip(self.samples.iter()) {
+                let sample = (sample * 32768.0).round().clamp(-32768.0, 32767.0) as i16;
+                bytes.copy_from_slice(&sample.to_ne_bytes());
+            }
+        }
+    }
+}
+
+/// Forward every packet the encoder has ready, returns false if the consumer is gone
+fn send_audio_packets(
+    encoder: &mut encoder::audio::Encoder,
+    worker_sender: &SlickscreenMessageSender,
+    stats: &StatsCounters,
+) -> bool {
+    let mut packet = ffmpeg_next::Packet::empty();
+    while let Ok(_) = encoder.receive_packet(&mut packet) {
+        match worker_sender.send(SlickscreenMessage::Audio(packet.clone())) {
+            Ok(dropped) => StatsCounters::add(&stats.packets_dropped, dropped),
+            Err(e) => {
+                println!(
+                    "Unable to send encoded audio packet. Audio encoder worker exiting. - {}",
+                    e
+                );
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// Frames allocated up front for the capture callback, which runs on a real-time thread and
+/// must not allocate. The encoder thread hands every frame back once it is done with it
+#[derive(Clone)]
+struct AudioFramePool {
+    free_sender: crossbeam::channel::Sender<AudioFrame>,
+    free_receiver: crossbeam::channel::Receiver<AudioFrame>,
+    format: ffmpeg_sample::Sample,
+    samples: usize,
+    channel_layout: ChannelLayout,
+    sample_rate: u32,
+}
+
+impl AudioFramePool {
+    /// `capacity` frames of up to `samples` samples each
+    fn new(
+        format: ffmpeg_sample::Sample,
+        samples: usize,
+        channel_layout: ChannelLayout,
+        sample_rate: u32,
+        capacity: usize,
+    ) -> Self {
+        let (free_sender, free_receiver) = crossbeam::channel::bounded(capacity);
+        let pool = Self {
+            free_sender,
+            free_receiver,
+            format,
+            samples,
+            channel_layout,
+            sample_rate,
+        };
+        for _ in 0..capacity {
+            let _ = pool.free_sender.try_send(pool.allocate());
+        }
+        pool
+    }
+
+    fn allocate(&self) -> AudioFrame {
+        let mut frame = AudioFrame::new(self.format, self.samples, self.channel_layout);
+        // The filter graph source rejects frames without a rate
+        frame.set_rate(self.sample_rate);
+        frame
+    }
+
+    /// A free frame holding `samples` samples, or None if the encoder thread has all of them.
+    /// Never allocates
+    fn try_get(&self, samples: usize) -> Option<AudioFrame> {
+        let mut frame = self.free_receiver.try_recv().ok()?;
+        frame.set_samples(samples.min(self.samples));
+        Some(frame)
+    }
+
+    /// Return a frame. The encoder or the filter graph may still hold a reference to its
+    /// buffer, such a frame is replaced with a new one instead of being overwritten
+    fn put(&self, mut frame: AudioFrame) {
+        if unsafe { ffmpeg_next::ffi::av_frame_is_writable(frame.as_mut_ptr()) } == 0 {
+            frame = self.allocate();
+        }
+        let _ = self.free_sender.try_send(frame);
+    }
+}
+
+/// Process and encode a frame and forward its packets, returns false if the worker must
+/// stop
+fn encode_audio_frame(
+    encoder: &mut encoder::audio::Encoder,
+    processor: &mut AudioProcessor,
+    frame: &mut AudioFrame,
+    worker_sender: &SlickscreenMessageSender,
+    stats: &StatsCounters,
+) -> bool {
+    processor.process(frame);
+    if let Err(e) = encoder.send_frame(frame) {
+        println!("Error while encoding audio frame: {}", e);
+        return false;
+    }
+    send_audio_packets(encoder, worker_sender, stats)
+}
+
 pub(super) struct AudioRecorder {
     pub worker: worker::Worker<AudioRecorderMessage>,
+    pub stream_description: StreamDescription,
     stream: cpal::Stream,
 }
 
@@ -32,6 +509,8 @@ impl AudioRecorder {
     pub fn new(
         time_reference: SlickscreenTime,
         slickscreen_message_sender: SlickscreenMessageSender,
+        slickscreen_config: &SlickscreenConfig,
+        stats: std::sync::Arc<StatsCounters>,
     ) -> Result<Self, SlickscreenError> {
         let default_device = cpal::default_host()
             .default_output_device()
@@ -56,41 +535,119 @@ impl AudioRecorder {
         encoder.set_channel_layout(encoder_channel_layout);
         // find_by_name("libfdk_aac") - interleaved S16
         // find_by_name("libopus") - interleaved S16
+        let codec =
+            encoder::find_by_name("libfdk_aac").ok_or(SlickscreenError::AudioEncoderNotFound)?;
         let mut encoder = encoder
-            .open_as(
-                encoder::find_by_name("libfdk_aac")
-                    .ok_or(SlickscreenError::AudioEncoderNotFound)?,
-            )
+            .open_as(codec)
             .map_err(|_e| SlickscreenError::AudioEncoderNotFound)?;
+        let stream_description = StreamDescription {
+            kind: StreamKind::Audio,
+            codec,
+            parameters: ffmpeg_next::codec::Parameters::from(&encoder),
+            time_base: ffmpeg_next::util::rational::Rational::new(1, 1000000),
+            info: StreamInfo {
+                kind: StreamKind::Audio,
+                codec: codec.name().to_string(),
+                width: None,
+                height: None,
+                sample_rate: Some(sample_rate as u32),
+                channels: Some(channel_count as u32),
+            },
+        };
 
-        let worker = worker::Worker::new(
+        let mut audio_filter = match &slickscreen_config.audio_filter {
+            Some(spec) => Some(AudioFilter::new(
+                spec,
+                encoder_format,
+                sample_rate as u32,
+                encoder_channel_layout,
+                encoder.frame_size(),
+            )?),
+            None => None,
+        };
+
+        let mut processor = AudioProcessor::new(
+            slickscreen_config,
+            sample_rate as u32,
+            channel_count,
+            stats.clone(),
+        );
+
+        let frame_pool = AudioFramePool::new(
+            encoder_format,
+            sample_count,
+            encoder_channel_layout,
+            sample_rate as u32,
+            AUDIO_QUEUE_CAPACITY,
+        );
+        let encoder_frame_pool = frame_pool.clone();
+        let encoder_stats = stats.clone();
+        let worker = worker::Worker::new_with_capacity(
             slickscreen_message_sender,
             move |worker_sender: SlickscreenMessageSender,
                   control_receiver: crossbeam::channel::Receiver<AudioRecorderMessage>| {
                 for msg in control_receiver.iter() {
                     match msg {
                         AudioRecorderMessage::Quit => {
+                            if let Some(audio_filter) = audio_filter.as_mut() {
+                                // Audio held back by the filter, e.g. the lookahead of loudnorm
+                                if let Err(e) = audio_filter.flush() {
+                                    println!("Error while flushing audio filter: {}", e);
+                                }
+                                while let Some(mut filtered_frame) = audio_filter.pull() {
+                                    if !encode_audio_frame(
+                                        &mut encoder,
+                                        &mut processor,
+                                        &mut filtered_frame,
+                                        &worker_sender,
+                                        &encoder_stats,
+                                    ) {
+                                        return;
+                                    }
+                                }
+                            }
                             return;
                         }
-                        AudioRecorderMessage::RawAudioPacket(_pts, frame) => {
-                            if let Err(e) = encoder.send_frame(&frame) {
-                                println!("Error while encoding audio frame: {}", e);
-                                return;
-                            }
-
-                            let mut packet = ffmpeg_next::Packet::empty();
-                            while let Ok(_) = encoder.receive_packet(&mut packet) {
-                                if let Err(e) =
-                                    worker_sender.send(SlickscreenMessage::Audio(packet.clone()))
-                                {
-                                    println!("Unable to send encoded audio packet. Audio encoder worker exiting. - {}", e);
-                                    return;
+                        AudioRecorderMessage::RawAudioPacket(_pts, mut frame) => {
+                            match audio_filter.as_mut() {
+                                Some(audio_filter) => {
+                                    let result = audio_filter.push(&frame);
+                                    encoder_frame_pool.put(frame);
+                                    if let Err(e) = result {
+                                        println!("Error while filtering audio frame: {}", e);
+                                        return;
+                                    }
+                                    while let Some(mut filtered_frame) = audio_filter.pull() {
+                                        if !encode_audio_frame(
+                                            &mut encoder,
+                                            &mut processor,
+                                            &mut filtered_frame,
+                                            &worker_sender,
+                                            &encoder_stats,
+                                        ) {
+                                            return;
+                                        }
+                                    }
+                                }
+                                None => {
+                                    let encoded = encode_audio_frame(
+                                        &mut encoder,
+                                        &mut processor,
+                                        &mut frame,
+                                        &worker_sender,
+                                        &encoder_stats,
+                                    );
+                                    encoder_frame_pool.put(frame);
+                                    if !encoded {
+                                        return;
+                                    }
                                 }
                             }
                         }
                     }
                 }
             },
+            AUDIO_QUEUE_CAPACITY,
         );
 
         let worker_sender = worker.control_sender();
@@ -99,20 +656,49 @@ impl AudioRecorder {
                 &config,
                 cpal::SampleFormat::I16,
                 move |data: &cpal::Data, _input_info: &cpal::InputCallbackInfo| {
-                    let now = time_reference.pts_now();
-
-                    let mut frame = AudioFrame::new(
-                        encoder_format,
-                        data.len() / channel_count,
-                        encoder_channel_layout,
-                    );
-                    frame.set_pts(Some(now));
-
-                    frame.data_mut(0)[0..data.bytes().len()].copy_from_slice(data.bytes());
-                    if let Err(e) =
-                        worker_sender.send(AudioRecorderMessage::RawAudioPacket(now, frame))
+                    let now = match time_reference.running_pts() {
+                        Some(now) => now,
+                        None => return,
+                    };
+
+                    // Buffers larger than requested are split, every frame in the pool
+                    // holds at most `sample_count` samples
+                    let bytes_per_sample = channel_count * 2;
+                    for (index, chunk) in data
+                        .bytes()
+                        .chunks(sample_count * bytes_per_sample)
+                        .enumerate()
                     {
-                        println!("Audio recorder worker thread appears to be dead. - {:?}", e);
+                        let pts = now + (index * sample_count * 1000000 / sample_rate) as i64;
+                        StatsCounters::add(&stats.audio_frames_captured, 1);
+                        let mut frame = match frame_pool.try_get(chunk.len() / bytes_per_sample) {
+                            Some(frame) => frame,
+                            None => {
+                                StatsCounters::add(&stats.audio_frames_dropped, 1);
+                                continue;
+                            }
+                        };
+                        frame.set_pts(Some(pts));
+                        frame.data_mut(0)[0..chunk.len()].copy_from_slice(chunk);
+                        // This runs on the real-time audio thread, so it must never block
+                        match worker_sender
+                            .try_send(AudioRecorderMessage::RawAudioPacket(pts, frame))
+                        {
+                            Ok(()) => {}
+                            Err(crossbeam::channel::TrySendError::Full(
+                                AudioRecorderMessage::RawAudioPacket(_, frame),
+                            )) => {
+                                // Never sent anywhere, so putting it back does not allocate
+                                frame_pool.put(frame);
+                                StatsCounters::add(&stats.audio_frames_dropped, 1);
+                            }
+                            Err(e) => {
+                                println!(
+                                    "Audio recorder worker thread appears to be dead. - {:?}",
+                                    e
+                                );
+                            }
+                        }
                     }
                 },
                 move |err| {
@@ -123,6 +709,157 @@ impl AudioRecorder {
 
         stream.play().map_err(|_e| SlickscreenError::Unexpected)?;
 
-        Ok(Self { worker, stream })
+        Ok(Self {
+            worker,
+            stream_description,
+            stream,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+    const CHANNELS: usize = 2;
+
+    /// `seconds` of a sine wave with a peak of `peak_db` dBFS on every channel, interleaved
+    fn tone(frequency: f64, peak_db: f64, seconds: f64) -> Vec<f32> {
+        let amplitude = db_to_gain(peak_db);
+        let frames = (seconds * SAMPLE_RATE as f64) as usize;
+        (0..frames)
+            .flat_map(|frame| {
+                let phase = 2.0 * std::f64::consts::PI * frequency * frame as f64;
+                let sample = (amplitude * (phase / SAMPLE_RATE as f64).sin()) as f32;
+                std::iter::repeat(sample).take(CHANNELS)
+            })
+            .collect()
+    }
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples
+            .iter()
+            .fold(0f32, |peak, sample| peak.max(sample.abs()))
+    }
+
+    #[test]
+    fn meter_reads_reference_tone() {
+        let mut meter = LoudnessMeter::new(SAMPLE_RATE, CHANNELS);
+        assert!(meter.add(&tone(997.0, -23.0, 20.0)));
+        let loudness = meter.integrated_loudness().unwrap();
+        assert!((loudness + 23.0).abs() < 0.1, "{} LUFS", loudness);
+    }
+
+    #[test]
+    fn meter_ignores_silence() {
+        let mut meter = LoudnessMeter::new(SAMPLE_RATE, CHANNELS);
+        assert!(!meter.add(&vec![0.0; SAMPLE_RATE as usize * CHANNELS * 5]));
+        assert_eq!(meter.integrated_loudness(), None);
+    }
+
+    #[test]
+    fn noise_gate_silences_quiet_audio() {
+        let mut gate = NoiseGate::new(&NoiseGateConfig::default(), SAMPLE_RATE);
+        let mut samples = tone(440.0, -60.0, 1.0);
+        gate.process(&mut samples, CHANNELS);
+        assert!(peak(&samples) < db_to_gain(-100.0) as f32);
+    }
+
+    #[test]
+    fn noise_gate_opens_and_closes_in_time() {
+        let config = NoiseGateConfig::default();
+        let mut gate = NoiseGate::new(&config, SAMPLE_RATE);
+        let frames_in = |time: std::time::Duration| {
+            (time.as_secs_f64() * SAMPLE_RATE as f64) as usize * CHANNELS
+        };
+
+        // Opens within a few attack times
+        let loud = tone(440.0, -20.0, 1.0);
+        let mut samples = loud.clone();
+        let (early, opened) = (frames_in(config.attack / 5), frames_in(config.attack * 5));
+        gate.process(&mut samples[..early], CHANNELS);
+        assert!(gate.gain < 0.5);
+        gate.process(&mut samples[early..opened], CHANNELS);
+        assert!(gate.gain > 0.99);
+        gate.process(&mut samples[opened..], CHANNELS);
+        // Once open the audio passes untouched
+        let mut open = loud.clone();
+        gate.process(&mut open, CHANNELS);
+        assert!(open
+            .iter()
+            .zip(loud.iter())
+            .all(|(a, b)| (a - b).abs() < 1e-4));
+
+        // Stays open for the tail of a word, then closes within a few release times
+        let mut quiet = tone(440.0, -60.0, 2.0);
+        let hold = frames_in(config.release / 2);
+        gate.process(&mut quiet[..hold], CHANNELS);
+        assert!(gate.gain > 0.5);
+        gate.process(&mut quiet[hold..], CHANNELS);
+        assert!(gate.gain < 0.001);
+    }
+
+    #[test]
+    fn normalizer_reaches_target() {
+        let mut normalizer = LoudnessNormalizer::new(-16.0, SAMPLE_RATE, CHANNELS);
+        let mut output_meter = LoudnessMeter::new(SAMPLE_RATE, CHANNELS);
+        let samples = tone(997.0, -30.0, 30.0);
+        for (index, chunk) in samples.chunks(960 * CHANNELS).enumerate() {
+            let mut chunk = chunk.to_vec();
+            normalizer.process(&mut chunk, CHANNELS);
+            // Only measure once the input loudness has settled
+            if index >= 500 {
+                output_meter.add(&chunk);
+            }
+        }
+        let loudness = output_meter.integrated_loudness().unwrap();
+        assert!((loudness + 16.0).abs() < 0.1, "{} LUFS", loudness);
+    }
+
+    #[test]
+    fn normalizer_never_exceeds_ceiling() {
+        let ceiling = db_to_gain(LOUDNESS_PEAK_CEILING_DB) as f32;
+        let mut normalizer = LoudnessNormalizer::new(-16.0, SAMPLE_RATE, CHANNELS);
+        // A quiet tone that gets the most gain, with loud clicks that must not clip
+        let mut samples = tone(997.0, -40.0, 20.0);
+        for click in samples.chunks_mut(SAMPLE_RATE as usize / 3 * CHANNELS) {
+            click[..CHANNELS]
+                .iter_mut()
+                .for_each(|sample| *sample = 0.5);
+        }
+        for chunk in samples.chunks_mut(960 * CHANNELS) {
+            normalizer.process(chunk, CHANNELS);
+            assert!(peak(chunk) <= ceiling, "peak {}", peak(chunk));
+        }
+        assert!(normalizer.gain > 1.0);
+    }
+
+    #[test]
+    fn frame_pool_never_allocates_on_get() {
+        let pool = AudioFramePool::new(
+            ffmpeg_sample::Sample::I16(ffmpeg_sample::Type::Packed),
+            480,
+            ChannelLayout::STEREO,
+            SAMPLE_RATE,
+            2,
+        );
+        let first = pool.try_get(480).unwrap();
+        let second = pool.try_get(1000).unwrap();
+        assert_eq!(second.samples(), 480);
+        assert!(pool.try_get(480).is_none());
+
+        // A frame whose buffer is still referenced elsewhere comes back as a new frame
+        let mut referenced = AudioFrame::empty();
+        unsafe { ffmpeg_next::ffi::av_frame_ref(referenced.as_mut_ptr(), first.as_ptr()) };
+        let first_buffer = first.data(0).as_ptr();
+        pool.put(first);
+        pool.put(second);
+        let recycled = [pool.try_get(240).unwrap(), pool.try_get(240).unwrap()];
+        assert!(recycled
+            .iter()
+            .all(|frame| frame.data(0).as_ptr() != first_buffer));
+        assert!(recycled.iter().all(|frame| frame.rate() == SAMPLE_RATE));
+        assert_eq!(referenced.data(0).as_ptr(), first_buffer);
     }
 }
@@ -0,0 +1,243 @@
+//! A line based JSON protocol for controlling a running capture over a Unix domain socket.
+//!
+//! Every request is a single line such as `{"command":"pause"}` and is answered with a
+//! single line such as `{"ok":true,"status":{...}}`.
+
+use slickscreen::{Marker, MaskRegion, SlickscreenControl, SlickscreenStatus};
+
+use anyhow::{anyhow, Result};
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+
+#[derive(Subcommand, Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum ControlRequest {
+    /// Start recording a capture that was started with --paused, same as resume
+    Start,
+    /// Stop the capture and finalize its outputs
+    Stop,
+    /// Pause the capture, the paused time is cut from the recording
+    Pause,
+    /// Resume a paused capture
+    Resume,
+    /// Print the state of the capture
+    Status,
+    /// Mark the current point in the recording, it becomes a chapter in the output file
+    Marker { label: String },
+    /// Encode the next frame as a keyframe
+    Keyframe,
+    /// Hide a region from the recording, [NAME=]X,Y,WIDTHxHEIGHT[:blur|black]. Replaces the
+    /// mask of the same name
+    Mask { region: MaskRegion },
+    /// Remove a mask
+    Unmask { name: String },
+    /// Turn a mask back on
+    #[serde(rename = "enable-mask")]
+    EnableMask { name: String },
+    /// Turn a mask off without removing it
+    #[serde(rename = "disable-mask")]
+    DisableMask { name: String },
+}
+
+#[derive(Serialize, Debug)]
+struct ControlResponse {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<SlickscreenStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    marker: Option<Marker>,
+}
+
+impl ControlResponse {
+    fn status(status: SlickscreenStatus) -> Self {
+        Self {
+            ok: true,
+            error: None,
+            status: Some(status),
+            marker: None,
+        }
+    }
+
+    fn error(error: String) -> Self {
+        Self {
+            ok: false,
+            error: Some(error),
+            status: None,
+            marker: None,
+        }
+    }
+}
+
+/// `$XDG_RUNTIME_DIR/slickscreen.sock`, falling back to a per user path in /tmp
+pub fn default_socket_path() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(runtime_dir) => Path::new(&runtime_dir).join("slickscreen.sock"),
+        None => {
+            let user = std::env::var("USER").unwrap_or_else(|_| "unknown".to_string());
+            PathBuf::from(format!("/tmp/slickscreen-{}.sock", user))
+        }
+    }
+}
+
+/// Removes the socket file when the capture ends
+pub struct ControlSocket {
+    path: PathBuf,
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Listen for control requests on `path`.
+///
+/// Stopping through the socket sends on `stop_sender`, the same way Ctrl+C does.
+pub fn serve(
+    path: &Path,
+    control: SlickscreenControl,
+    stop_sender: Sender<()>,
+) -> Result<ControlSocket> {
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(anyhow!(
+                "{} is in use by another capture",
+                path.to_string_lossy()
+            ));
+        }
+        // Left behind by a capture that did not exit cleanly
+        std::fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+    println!(
+        "Listening for control commands on {}",
+        path.to_string_lossy()
+    );
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    println!("Error while accepting control connection: {}", e);
+                    continue;
+                }
+            };
+            let control = control.clone();
+            let stop_sender = stop_sender.clone();
+            std::thread::spawn(move || {
+                if let Err(e) = handle_connection(stream, &control, &stop_sender) {
+                    println!("Error on control connection: {}", e);
+                }
+            });
+        }
+    });
+
+    Ok(ControlSocket {
+        path: path.to_path_buf(),
+    })
+}
+
+fn handle_connection(
+    stream: UnixStream,
+    control: &SlickscreenControl,
+    stop_sender: &Sender<()>,
+) -> Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str::<ControlRequest>(&line) {
+            Ok(request) => dispatch(request, control, stop_sender),
+            Err(e) => ControlResponse::error(format!("invalid request: {}", e)),
+        };
+        serde_json::to_writer(&mut writer, &response)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+fn dispatch(
+    request: ControlRequest,
+    control: &SlickscreenControl,
+    stop_sender: &Sender<()>,
+) -> ControlResponse {
+    match request {
+        ControlRequest::Start | ControlRequest::Resume => {
+            control.resume();
+        }
+        ControlRequest::Pause => {
+            control.pause();
+        }
+        ControlRequest::Stop => {
+            let status = control.status();
+            let _ = stop_sender.send(());
+            return ControlResponse::status(status);
+        }
+        ControlRequest::Status => {}
+        ControlRequest::Keyframe => {
+            control.request_keyframe();
+        }
+        ControlRequest::Mask { region } => {
+            control.set_mask(region);
+        }
+        ControlRequest::Unmask { name } => {
+            if !control.remove_mask(&name) {
+                return ControlResponse::error(format!("no mask named {}", name));
+            }
+        }
+        ControlRequest::EnableMask { name } => {
+            if !control.set_mask_enabled(&name, true) {
+                return ControlResponse::error(format!("no mask named {}", name));
+            }
+        }
+        ControlRequest::DisableMask { name } => {
+            if !control.set_mask_enabled(&name, false) {
+                return ControlResponse::error(format!("no mask named {}", name));
+            }
+        }
+        ControlRequest::Marker { label } => {
+            let marker = control.add_marker(&label);
+            return ControlResponse {
+                marker: Some(marker),
+                ..ControlResponse::status(control.status())
+            };
+        }
+    }
+    ControlResponse::status(control.status())
+}
+
+/// Send a single request to a running capture and print the response.
+///
+/// Fails if the capture reported an error.
+pub fn send(path: &Path, request: &ControlRequest) -> Result<()> {
+    let mut stream = UnixStream::connect(path).map_err(|e| {
+        anyhow!(
+            "unable to connect to a running capture at {}: {}",
+            path.to_string_lossy(),
+            e
+        )
+    })?;
+    serde_json::to_writer(&mut stream, request)?;
+    stream.write_all(b"\n")?;
+
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response)?;
+    println!("{}", response.trim_end());
+
+    let response: serde_json::Value = serde_json::from_str(&response)?;
+    match response.get("ok").and_then(|ok| ok.as_bool()) {
+        Some(true) => Ok(()),
+        _ => Err(anyhow!("the capture rejected the command")),
+    }
+}
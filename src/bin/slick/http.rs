@@ -0,0 +1,199 @@
+use slickscreen::{MaskRegion, SlickscreenControl};
+
+use anyhow::{anyhow, Result};
+use std::io::Read;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::Duration;
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+
+const MJPEG_BOUNDARY: &str = "slickscreen-preview";
+
+const INDEX_HTML: &str = r#"<!DOCTYPE html>
+<html>
+<head><title>Slickscreen</title></head>
+<body>
+<img src="/preview.mjpeg" style="max-width: 100%">
+<p>
+<button onclick="fetch('/pause', {method: 'POST'})">Pause</button>
+<button onclick="fetch('/resume', {method: 'POST'})">Resume</button>
+<button onclick="fetch('/stop', {method: 'POST'})">Stop</button>
+<input id="label" placeholder="Marker label">
+<button onclick="fetch('/marker', {method: 'POST', body: document.getElementById('label').value})">Mark</button>
+</p>
+<pre id="status"></pre>
+<script>
+setInterval(() => fetch('/status').then(r => r.text()).then(t => {
+    document.getElementById('status').textContent = t;
+}), 1000);
+</script>
+</body>
+</html>
+"#;
+
+/// Serves the live preview and a small JSON control api for a running recording.
+///
+/// Stopping through the api sends on `stop_sender`, the same way Ctrl+C does.
+pub fn serve(address: &str, control: SlickscreenControl, stop_sender: Sender<()>) -> Result<()> {
+    let server = Server::http(address).map_err(|e| anyhow!("{}: {}", address, e))?;
+    println!("Serving live preview on http://{}/", address);
+
+    std::thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let control = control.clone();
+            let stop_sender = stop_sender.clone();
+            // The preview stream never ends, so every request gets its own thread
+            std::thread::spawn(move || {
+                if let Err(e) = handle_request(request, &control, &stop_sender) {
+                    println!("Error while responding to http request: {}", e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("static header is valid")
+}
+
+fn json_response<T: serde::Serialize>(value: &T) -> Response<std::io::Cursor<Vec<u8>>> {
+    Response::from_data(serde_json::to_vec(value).expect("status is serializable"))
+        .with_header(header("Content-Type", "application/json"))
+}
+
+fn handle_request(
+    mut request: Request,
+    control: &SlickscreenControl,
+    stop_sender: &Sender<()>,
+) -> std::io::Result<()> {
+    match (request.method(), request.url()) {
+        (Method::Get, "/") => request.respond(
+            Response::from_string(INDEX_HTML).with_header(header("Content-Type", "text/html")),
+        ),
+        (Method::Get, "/status") => request.respond(json_response(&control.status())),
+        (Method::Get, "/preview.jpg") => match control.preview_jpeg() {
+            Some((_, jpeg)) => request.respond(
+                Response::from_data(jpeg.as_slice())
+                    .with_header(header("Content-Type", "image/jpeg")),
+            ),
+            None => request.respond(Response::empty(StatusCode(503))),
+        },
+        (Method::Get, "/preview.mjpeg") => request.respond(Response::new(
+            StatusCode(200),
+            vec![header(
+                "Content-Type",
+                &format!("multipart/x-mixed-replace; boundary={}", MJPEG_BOUNDARY),
+            )],
+            MjpegStream::new(control.clone()),
+            None,
+            None,
+        )),
+        (Method::Post, "/pause") => {
+            control.pause();
+            request.respond(json_response(&control.status()))
+        }
+        (Method::Post, "/resume") => {
+            control.resume();
+            request.respond(json_response(&control.status()))
+        }
+        (Method::Post, "/marker") => {
+            // The request body is the label of the marker
+            let mut label = String::new();
+            request.as_reader().read_to_string(&mut label)?;
+            request.respond(json_response(&control.add_marker(label.trim())))
+        }
+        (Method::Post, "/keyframe") => {
+            control.request_keyframe();
+            request.respond(json_response(&control.status()))
+        }
+        (Method::Get, "/masks") => request.respond(json_response(&control.masks())),
+        (Method::Post, "/masks") => {
+            // The request body is a JSON mask, e.g.
+            // {"name":"chat","x":0,"y":0,"width":640,"height":480}
+            let mut body = String::new();
+            request.as_reader().read_to_string(&mut body)?;
+            match serde_json::from_str::<MaskRegion>(&body) {
+                Ok(region) => request.respond(json_response(&control.set_mask(region))),
+                Err(e) => request.respond(
+                    Response::from_string(format!("invalid mask: {}", e))
+                        .with_status_code(StatusCode(400)),
+                ),
+            }
+        }
+        (method, url) if url.starts_with("/masks/") => {
+            let path = url["/masks/".len()..].to_string();
+            let found = match (method, path.split_once('/')) {
+                (Method::Delete, None) => control.remove_mask(&path),
+                (Method::Post, Some((name, "enable"))) => control.set_mask_enabled(name, true),
+                (Method::Post, Some((name, "disable"))) => control.set_mask_enabled(name, false),
+                _ => false,
+            };
+            if found {
+                request.respond(json_response(&control.masks()))
+            } else {
+                request.respond(Response::empty(StatusCode(404)))
+            }
+        }
+        (Method::Post, "/stop") => {
+            let status = control.status();
+            let _ = stop_sender.send(());
+            request.respond(json_response(&status))
+        }
+        _ => request.respond(Response::empty(StatusCode(404))),
+    }
+}
+
+/// An endless multipart body with a part for every new preview image
+struct MjpegStream {
+    control: SlickscreenControl,
+    sequence: u64,
+    pending: Vec<u8>,
+    position: usize,
+}
+
+impl MjpegStream {
+    fn new(control: SlickscreenControl) -> Self {
+        Self {
+            control,
+            sequence: 0,
+            pending: Vec::new(),
+            position: 0,
+        }
+    }
+
+    fn queue_part(&mut self, jpeg: Arc<Vec<u8>>) {
+        self.pending.clear();
+        self.position = 0;
+        self.pending.extend_from_slice(
+            format!(
+                "--{}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+                MJPEG_BOUNDARY,
+                jpeg.len()
+            )
+            .as_bytes(),
+        );
+        self.pending.extend_from_slice(&jpeg);
+        self.pending.extend_from_slice(b"\r\n");
+    }
+}
+
+impl Read for MjpegStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.position >= self.pending.len() {
+            // Keep waiting while paused, the stream lasts until the process exits
+            if let Some((sequence, jpeg)) = self
+                .control
+                .next_preview_jpeg(self.sequence, Duration::from_secs(1))
+            {
+                self.sequence = sequence;
+                self.queue_part(jpeg);
+            }
+        }
+
+        let count = buf.len().min(self.pending.len() - self.position);
+        buf[..count].copy_from_slice(&self.pending[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
@@ -0,0 +1,490 @@
+#[cfg(unix)]
+mod ctl;
+#[cfg(feature = "http")]
+mod http;
+
+use slickscreen::{
+    CameraConfig, CameraDevice, ChromaFormat, ColorRange, CursorMode, DropPolicy, EncoderProfile,
+    EncoderThreading, InputOverlayConfig, MaskRegion, NoiseGateConfig, OverlayPosition,
+    RateControl, Slickscreen, SlickscreenConfig, SlickscreenOutput, SrtMode, SubtitleFormat,
+    TextOverlayConfig,
+};
+
+use anyhow::Result;
+use clap::{ArgEnum, Args, Parser, Subcommand};
+use ctrlc;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::Duration;
+
+use scrap::Display;
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+#[clap(propagate_version = true)]
+/// A tool for capturing your screen and system audio
+struct Cli {
+    #[clap(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    FileCapture(FileCaptureArguments),
+    StreamCapture(StreamCaptureArguments),
+    ListScreens,
+    #[cfg(unix)]
+    Ctl(CtlArguments),
+}
+
+#[derive(Args, Debug)]
+/// Record the screen capture to a file
+///
+/// The default is VP8 and Opus in a Vorbis container
+struct FileCaptureArguments {
+    #[clap(long, short = 'o')]
+    output_file: String,
+    /// Additional output to send the same recording to at the same time, e.g.
+    /// rtmp://host/app/key, srt://host:port or live/index.m3u8
+    #[clap(long)]
+    tee: Vec<SlickscreenOutput>,
+    #[clap(flatten)]
+    session: SessionArguments,
+}
+
+#[derive(Args, Debug)]
+/// Options shared by all capture commands
+struct SessionArguments {
+    /// Title stored in the container metadata
+    #[clap(long)]
+    title: Option<String>,
+    /// Author stored in the container metadata
+    #[clap(long)]
+    author: Option<String>,
+    /// Write a JSON description of the recording to this file when it stops
+    #[clap(long)]
+    manifest: Option<String>,
+    /// Do not capture anything until resumed, e.g. with `slick ctl start`
+    #[clap(long)]
+    paused: bool,
+    /// Listen for `slick ctl` commands on this socket instead of the default one
+    #[cfg(unix)]
+    #[clap(long)]
+    socket: Option<std::path::PathBuf>,
+    /// Do not listen for `slick ctl` commands
+    #[cfg(unix)]
+    #[clap(long, conflicts_with = "socket")]
+    no_socket: bool,
+    /// Serve a live preview and control api over http on this address, e.g. 0.0.0.0:8080
+    #[cfg(feature = "http")]
+    #[clap(long)]
+    http: Option<String>,
+    /// Mouse cursor in the recording: hide, show or enlarge
+    #[clap(long, default_value = "show")]
+    cursor: CursorMode,
+    /// Draw an expanding ring where the mouse is clicked
+    #[clap(long)]
+    show_clicks: bool,
+    /// Show recent key presses as a caption, needs --font
+    #[clap(long, requires = "font")]
+    show_keys: bool,
+    /// TrueType or OpenType font for text drawn onto the recording
+    #[clap(long)]
+    font: Option<std::path::PathBuf>,
+    /// Record clicks and key presses as a subtitle track that viewers can toggle
+    #[clap(long)]
+    log_input: bool,
+    /// Subtitle codec of the input log: webvtt, srt or mov_text, defaults to one that suits
+    /// the output file
+    #[clap(long, requires = "log_input")]
+    log_input_format: Option<SubtitleFormat>,
+    /// Burn the local time into every frame, optionally with a strftime style format where
+    /// {hostname} is replaced by the host name. Needs --font
+    #[clap(long, requires = "font")]
+    burn_in: Option<Option<String>>,
+    /// Corner for --burn-in: top-left, top-right, bottom-left or bottom-right
+    #[clap(long, default_value = "bottom-right")]
+    burn_in_position: OverlayPosition,
+    /// Draw the --burn-in text without a box behind it
+    #[clap(long)]
+    burn_in_no_box: bool,
+    /// FFmpeg filter graph applied to the video before it is encoded, e.g. scale=1280:-2
+    #[clap(long)]
+    video_filter: Option<String>,
+    /// FFmpeg filter graph applied to the audio before it is encoded, e.g.
+    /// highpass=f=80,afftdn
+    #[clap(long)]
+    audio_filter: Option<String>,
+    /// Silence the audio while it is quieter than this many dBFS, e.g. -45
+    #[clap(long, allow_hyphen_values = true)]
+    noise_gate: Option<f32>,
+    /// Milliseconds for the --noise-gate to open
+    #[clap(long, default_value = "5")]
+    noise_gate_attack: u64,
+    /// Milliseconds for the --noise-gate to close
+    #[clap(long, default_value = "150")]
+    noise_gate_release: u64,
+    /// Normalize the audio to this integrated loudness in LUFS, e.g. -16
+    #[clap(long, allow_hyphen_values = true)]
+    loudness: Option<f64>,
+    /// Show a camera in a corner of the recording, e.g. /dev/video0, or synthetic for test
+    /// bars
+    #[clap(long)]
+    camera: Option<CameraDevice>,
+    /// Corner for --camera: top-left, top-right, bottom-left or bottom-right
+    #[clap(long, default_value = "bottom-left")]
+    camera_position: OverlayPosition,
+    /// Width of the --camera picture in pixels
+    #[clap(long, default_value = "320")]
+    camera_width: u32,
+    /// Width of the border around the --camera picture, 0 for none
+    #[clap(long, default_value = "4")]
+    camera_border: u32,
+    /// Radius of the rounded corners of the --camera picture, 0 for square corners
+    #[clap(long, default_value = "16")]
+    camera_radius: u32,
+    /// Hide a region of the screen from the recording, e.g. --mask chat=1280,0,640x1080:blur.
+    /// Takes [NAME=]X,Y,WIDTHxHEIGHT[:blur|black] and can be repeated, masks are black by
+    /// default
+    #[clap(long = "mask")]
+    masks: Vec<MaskRegion>,
+    /// Height of text drawn onto the recording in pixels
+    #[clap(long, default_value = "32")]
+    font_size: f32,
+    /// Captured frames that may wait for the encoder
+    #[clap(long, default_value = "4")]
+    frame_queue: usize,
+    /// What to do when the encoder falls behind: drop-oldest, drop-newest or block capture
+    #[clap(long, default_value = "drop-oldest")]
+    frame_drop_policy: DropPolicy,
+    /// Encoded packets that may wait for the outputs
+    #[clap(long, default_value = "1000")]
+    packet_queue: usize,
+    /// What to do when the outputs fall behind: drop-oldest, drop-newest or block encoding
+    #[clap(long, default_value = "block")]
+    packet_drop_policy: DropPolicy,
+    #[clap(flatten)]
+    encoder: EncoderArguments,
+}
+
+/// Parses bits per second with an optional k, M or G suffix, e.g. 6M
+fn parse_bitrate(s: &str) -> Result<u64, String> {
+    let (digits, multiplier) = match s.chars().last() {
+        Some('k') | Some('K') => (&s[..s.len() - 1], 1_000),
+        Some('m') | Some('M') => (&s[..s.len() - 1], 1_000_000),
+        Some('g') | Some('G') => (&s[..s.len() - 1], 1_000_000_000),
+        _ => (s, 1),
+    };
+    digits
+        .parse::<f64>()
+        .map(|value| (value * multiplier as f64) as u64)
+        .map_err(|e| format!("invalid bitrate {}: {}", s, e))
+}
+
+/// Parses a positive number of seconds, e.g. 0.5
+fn parse_keyframe_interval(s: &str) -> Result<Duration, String> {
+    let seconds = s
+        .parse::<f64>()
+        .map_err(|e| format!("invalid keyframe interval {}: {}", s, e))?;
+    match Duration::try_from_secs_f64(seconds) {
+        Ok(interval) if !interval.is_zero() => Ok(interval),
+        _ => Err(format!(
+            "invalid keyframe interval {}, expected a positive number of seconds",
+            s
+        )),
+    }
+}
+
+/// Parses a `key=value` encoder option
+fn parse_encoder_option(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected key=value, got {}", s)),
+    }
+}
+
+#[derive(Args, Debug)]
+/// Video encoder settings
+struct EncoderArguments {
+    /// Encoder settings preset: archive, share, stream or lossless
+    #[clap(long, default_value = "stream")]
+    profile: EncoderProfile,
+    /// Raw encoder option applied after the profile, e.g. --vopt preset=veryfast
+    #[clap(long, parse(try_from_str = parse_encoder_option))]
+    vopt: Vec<(String, String)>,
+    /// Constant quality rate control, lower is better
+    #[clap(long, conflicts_with_all = &["qp", "bitrate"])]
+    crf: Option<u32>,
+    /// Constant quantizer rate control
+    #[clap(long, conflicts_with = "bitrate")]
+    qp: Option<u32>,
+    /// Average bitrate in bits per second, e.g. 6M
+    #[clap(long, parse(try_from_str = parse_bitrate))]
+    bitrate: Option<u64>,
+    /// Hold the bitrate constant instead of averaging it
+    #[clap(long, requires = "bitrate")]
+    cbr: bool,
+    /// VBV buffer size in bits for --cbr, defaults to two seconds worth of bitrate
+    #[clap(long, requires = "cbr", parse(try_from_str = parse_bitrate))]
+    vbv_buffer: Option<u64>,
+    /// Maximum seconds between two keyframes, lower values make seeking more precise
+    #[clap(long, default_value = "2", parse(try_from_str = parse_keyframe_interval))]
+    keyframe_interval: Duration,
+    /// Range of the encoded video: limited for the widest player support, or full
+    #[clap(long, default_value = "limited")]
+    color_range: ColorRange,
+    /// Pixel format of the video: yuv420, yuv444 for crisp text, rgb (x264rgb) or gbrp
+    /// (lossless FFV1, use an .mkv output)
+    #[clap(long, default_value = "yuv420")]
+    chroma: ChromaFormat,
+    /// Number of video encoder threads, 0 picks one based on the number of cores
+    #[clap(long, default_value = "0")]
+    threads: usize,
+    /// Encode several frames in parallel (frame) or split each frame (slice), slice
+    /// threading adds no latency
+    #[clap(long, default_value = "frame")]
+    threading: EncoderThreading,
+}
+
+impl EncoderArguments {
+    fn configure(&self, config: &mut SlickscreenConfig) {
+        config.profile = self.profile;
+        config.encoder_options = self.vopt.clone();
+        config.keyframe_interval = self.keyframe_interval;
+        config.color_range = self.color_range;
+        config.chroma_format = self.chroma;
+        config.encoder_threads = self.threads;
+        config.encoder_threading = self.threading;
+        config.rate_control = match (self.crf, self.qp, self.bitrate) {
+            (Some(crf), _, _) => Some(RateControl::Crf(crf)),
+            (_, Some(qp), _) => Some(RateControl::ConstantQp(qp)),
+            (_, _, Some(bitrate)) if self.cbr => Some(RateControl::Cbr {
+                bitrate,
+                vbv_buffer_size: self.vbv_buffer.unwrap_or(bitrate * 2),
+            }),
+            (_, _, Some(bitrate)) => Some(RateControl::Abr { bitrate }),
+            _ => None,
+        };
+    }
+}
+
+impl SessionArguments {
+    fn configure(&self, config: &mut SlickscreenConfig) {
+        config.start_paused = self.paused;
+        config.title = self.title.clone();
+        config.author = self.author.clone();
+        config.manifest_file = self.manifest.clone();
+        config.cursor = self.cursor;
+        if self.show_clicks || self.show_keys {
+            config.input_overlay = Some(InputOverlayConfig {
+                clicks: self.show_clicks,
+                keystrokes: self.show_keys,
+                font_path: self.font.clone(),
+                font_size: self.font_size,
+                ..InputOverlayConfig::default()
+            });
+        }
+        if let (Some(format), Some(font_path)) = (&self.burn_in, &self.font) {
+            config.text_overlay = Some(TextOverlayConfig {
+                format: format
+                    .clone()
+                    .unwrap_or_else(|| TextOverlayConfig::DEFAULT_FORMAT.to_string()),
+                font_path: font_path.clone(),
+                font_size: self.font_size,
+                position: self.burn_in_position,
+                background: !self.burn_in_no_box,
+            });
+        }
+        config.video_filter = self.video_filter.clone();
+        config.audio_filter = self.audio_filter.clone();
+        config.noise_gate = self.noise_gate.map(|threshold_db| NoiseGateConfig {
+            threshold_db,
+            attack: Duration::from_millis(self.noise_gate_attack),
+            release: Duration::from_millis(self.noise_gate_release),
+        });
+        config.loudness_target = self.loudness;
+        config.masks = self.masks.clone();
+        config.camera = self.camera.clone().map(|device| CameraConfig {
+            device,
+            position: self.camera_position,
+            width: self.camera_width,
+            border: self.camera_border,
+            corner_radius: self.camera_radius,
+        });
+        if self.log_input {
+            let format = self.log_input_format.or_else(|| {
+                config.outputs.iter().find_map(|output| match output {
+                    SlickscreenOutput::File(path) => SubtitleFormat::for_file(path),
+                    _ => None,
+                })
+            });
+            config.input_log = Some(format.unwrap_or(SubtitleFormat::WebVtt));
+        }
+        config.frame_queue_capacity = self.frame_queue;
+        config.frame_drop_policy = self.frame_drop_policy;
+        config.packet_queue_capacity = self.packet_queue;
+        config.packet_drop_policy = self.packet_drop_policy;
+        self.encoder.configure(config);
+        #[cfg(feature = "http")]
+        if self.http.is_some() {
+            config.preview = Some(slickscreen::PreviewConfig::default());
+        }
+    }
+}
+
+#[cfg(unix)]
+#[derive(Args, Debug)]
+/// Control a running capture
+struct CtlArguments {
+    /// Socket of the capture to control, defaults to $XDG_RUNTIME_DIR/slickscreen.sock
+    #[clap(long)]
+    socket: Option<std::path::PathBuf>,
+    #[clap(subcommand)]
+    command: ctl::ControlRequest,
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug)]
+enum StreamProtocol {
+    Srt,
+    Udp,
+    Rtp,
+}
+
+#[derive(Args, Debug)]
+/// Stream the screen capture as MPEG-TS over the network
+///
+/// Play it back with e.g. `ffplay srt://host:port` or add it as a media source in OBS
+struct StreamCaptureArguments {
+    /// host:port to send the stream to, or to listen on with --listen
+    address: String,
+    #[clap(long, arg_enum, default_value = "srt")]
+    protocol: StreamProtocol,
+    /// Wait for the receiver to connect instead of calling it (SRT only)
+    #[clap(long)]
+    listen: bool,
+    /// Receiver buffering in milliseconds (SRT only)
+    #[clap(long, default_value = "120")]
+    latency_ms: u64,
+    /// Size of each datagram, a multiple of the 188 byte MPEG-TS packet
+    #[clap(long, default_value = "1316")]
+    packet_size: usize,
+    /// Additional output to send the same recording to at the same time, e.g. capture.mp4
+    #[clap(long)]
+    tee: Vec<SlickscreenOutput>,
+    #[clap(flatten)]
+    session: SessionArguments,
+}
+
+impl StreamCaptureArguments {
+    fn output(&self) -> SlickscreenOutput {
+        let address = self.address.clone();
+        let packet_size = self.packet_size;
+        match self.protocol {
+            StreamProtocol::Srt => SlickscreenOutput::Srt {
+                address,
+                mode: if self.listen {
+                    SrtMode::Listener
+                } else {
+                    SrtMode::Caller
+                },
+                latency: Duration::from_millis(self.latency_ms),
+                packet_size,
+            },
+            StreamProtocol::Udp => SlickscreenOutput::Udp {
+                address,
+                packet_size,
+            },
+            StreamProtocol::Rtp => SlickscreenOutput::Rtp {
+                address,
+                packet_size,
+            },
+        }
+    }
+}
+
+fn capture_until_interrupted(
+    mut config: SlickscreenConfig,
+    session: &SessionArguments,
+    stop_tx: Sender<()>,
+    stop_rx: Receiver<()>,
+) -> Result<()> {
+    session.configure(&mut config);
+    let slick = Slickscreen::new(config)?;
+
+    #[cfg(feature = "http")]
+    if let Some(address) = &session.http {
+        http::serve(address, slick.control(), stop_tx.clone())?;
+    }
+    #[cfg(unix)]
+    let _control_socket = if session.no_socket {
+        None
+    } else {
+        let path = session
+            .socket
+            .clone()
+            .unwrap_or_else(ctl::default_socket_path);
+        match ctl::serve(&path, slick.control(), stop_tx.clone()) {
+            Ok(control_socket) => Some(control_socket),
+            Err(e) => {
+                println!("Control socket unavailable - {}", e);
+                None
+            }
+        }
+    };
+    drop(stop_tx);
+
+    stop_rx.recv()?;
+
+    println!("Stopping Slickscreen... ");
+    slick.stop()?;
+    println!("Slickscreen stopped - Exiting.");
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    let (stop_tx, stop_rx) = channel();
+    let ctrlc_tx = stop_tx.clone();
+    ctrlc::set_handler(move || {
+        ctrlc_tx
+            .send(())
+            .expect("Could not send ctrl+c signal to application.")
+    })?;
+
+    match &cli.command {
+        Commands::FileCapture(args) => {
+            let mut outputs = vec![SlickscreenOutput::File(args.output_file.clone())];
+            outputs.extend(args.tee.iter().cloned());
+            let config = SlickscreenConfig {
+                outputs,
+                ..SlickscreenConfig::default()
+            };
+            capture_until_interrupted(config, &args.session, stop_tx, stop_rx)?;
+        }
+        Commands::StreamCapture(args) => {
+            let mut outputs = vec![args.output()];
+            outputs.extend(args.tee.iter().cloned());
+            let config = SlickscreenConfig {
+                outputs,
+                ..SlickscreenConfig::default()
+            };
+            capture_until_interrupted(config, &args.session, stop_tx, stop_rx)?;
+        }
+        #[cfg(unix)]
+        Commands::Ctl(args) => {
+            let path = args.socket.clone().unwrap_or_else(ctl::default_socket_path);
+            ctl::send(&path, &args.command)?;
+        }
+        Commands::ListScreens => {
+            if let Ok(displays) = Display::all() {
+                for (i, display) in displays.iter().enumerate() {
+                    println!("{}: {}x{}", i, display.width(), display.height());
+                }
+            } else {
+                println!("No displays were found");
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,521 @@
+use super::*;
+
+use ffmpeg_next::util::error::EAGAIN;
+use ffmpeg_next::util::format::pixel::Pixel as ffmpeg_Pixel;
+use ffmpeg_next::util::frame::Video as VideoFrame;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::overlay::{BgraImage, Overlay};
+
+/// Distance between the picture-in-picture and the edges of the frame
+const CAMERA_MARGIN: i64 = 24;
+const CAMERA_BORDER_COLOR: [u8; 4] = [255, 255, 255, 255];
+/// Frame interval of the synthetic camera
+const SYNTHETIC_CAMERA_INTERVAL: std::time::Duration = std::time::Duration::from_millis(33);
+/// How long the camera thread waits for a picture before it checks whether to stop
+const CAMERA_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(5);
+
+/// Where the picture-in-picture comes from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CameraDevice {
+    /// A video4linux2 device such as /dev/video0, including v4l2loopback devices
+    V4l2(String),
+    /// Moving color bars, for trying out the picture-in-picture without a camera
+    Synthetic,
+}
+
+impl std::str::FromStr for CameraDevice {
+    type Err = SlickscreenError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" => Err(SlickscreenError::CameraError(
+                "no camera device".to_string(),
+            )),
+            "synthetic" => Ok(CameraDevice::Synthetic),
+            device => Ok(CameraDevice::V4l2(device.to_string())),
+        }
+    }
+}
+
+/// A camera drawn as a picture-in-picture on top of the screen, e.g. the presenter's face
+#[derive(Clone, Debug)]
+pub struct CameraConfig {
+    pub device: CameraDevice,
+    pub position: OverlayPosition,
+    /// Width of the picture in pixels, the height follows the aspect ratio of the camera
+    pub width: u32,
+    /// Width of the border around the picture in pixels, 0 for none
+    pub border: u32,
+    /// Radius of the rounded corners in pixels, 0 for square corners
+    pub corner_radius: u32,
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        CameraConfig {
+            device: CameraDevice::V4l2("/dev/video0".to_string()),
+            position: OverlayPosition::BottomLeft,
+            width: 320,
+            border: 4,
+            corner_radius: 16,
+        }
+    }
+}
+
+/// A camera picture scaled to the picture-in-picture size, tightly packed BGRA
+pub(crate) struct CameraImage {
+    width: usize,
+    height: usize,
+    data: Vec<u8>,
+}
+
+/// Produces camera pictures at the picture-in-picture size
+pub(crate) trait CameraSource {
+    /// Waits a short while for the next picture, None if it has not arrived yet. Never
+    /// blocks for long so the reading thread can be stopped
+    fn read(&mut self) -> Result<Option<CameraImage>, SlickscreenError>;
+}
+
+/// Height of a picture `width` pixels wide with the aspect ratio of `source_width` by
+/// `source_height`, rounded to an even number
+fn scaled_height(width: u32, source_width: u32, source_height: u32) -> u32 {
+    ((source_height as u64 * width as u64 / source_width.max(1) as u64) as u32 & !1).max(2)
+}
+
+/// Reads and decodes a video4linux2 device through libavdevice
+struct V4l2Camera {
+    device: String,
+    input: ffmpeg_next::format::context::Input,
+    stream_index: usize,
+    decoder: ffmpeg_next::decoder::Video,
+    width: u32,
+    scaler: Option<ffmpeg_next::software::scaling::Context>,
+}
+
+impl V4l2Camera {
+    fn open(device: &str, width: u32) -> Result<Self, SlickscreenError> {
+        let error =
+            |e: ffmpeg_next::Error| SlickscreenError::CameraError(format!("{}: {}", device, e));
+
+        ffmpeg_next::device::register_all();
+        let format = ffmpeg_next::device::input::video()
+            .find(|format| format.name() == "video4linux2,v4l2")
+            .ok_or(ffmpeg_next::Error::DemuxerNotFound)
+            .map_err(error)?;
+        let mut options = ffmpeg_next::Dictionary::new();
+        // Reading a camera that stopped delivering pictures would otherwise block forever
+        options.set("fflags", "nonblock");
+        let input = ffmpeg_next::format::open_with(device, &format, options)
+            .map_err(error)?
+            .input();
+        let stream = input
+            .streams()
+            .best(ffmpeg_next::media::Type::Video)
+            .ok_or(ffmpeg_next::Error::StreamNotFound)
+            .map_err(error)?;
+        let stream_index = stream.index();
+        let decoder = ffmpeg_next::codec::context::Context::from_parameters(stream.parameters())
+            .and_then(|context| context.decoder().video())
+            .map_err(error)?;
+
+        Ok(Self {
+            device: device.to_string(),
+            input,
+            stream_index,
+            decoder,
+            width,
+            scaler: None,
+        })
+    }
+
+    fn scale(&mut self, frame: &VideoFrame) -> Result<CameraImage, ffmpeg_next::Error> {
+        // Cameras may switch formats, e.g. when another application changes the resolution
+        let reusable = self.scaler.as_ref().map_or(false, |scaler| {
+            let input = scaler.input();
+            input.format == frame.format()
+                && input.width == frame.width()
+                && input.height == frame.height()
+        });
+        if !reusable {
+            self.scaler = Some(ffmpeg_next::software::scaling::Context::get(
+                frame.format(),
+                frame.width(),
+                frame.height(),
+                ffmpeg_Pixel::BGRA,
+                self.width,
+                scaled_height(self.width, frame.width(), frame.height()),
+                ffmpeg_next::software::scaling::Flags::BILINEAR,
+            )?);
+        }
+        let scaler = self.scaler.as_mut().expect("scaler was just created");
+
+        let (width, height) = (scaler.output().width, scaler.output().height);
+        let mut scaled = VideoFrame::new(ffmpeg_Pixel::BGRA, width, height);
+        scaler.run(frame, &mut scaled)?;
+
+        let (width, height) = (width as usize, height as usize);
+        let stride = scaled.stride(0);
+        let mut data = Vec::with_capacity(width * height * 4);
+        for row in scaled.data(0).chunks(stride).take(height) {
+            data.extend_from_slice(&row[..width * 4]);
+        }
+        Ok(CameraImage {
+            width,
+            height,
+            data,
+        })
+    }
+}
+
+impl CameraSource for V4l2Camera {
+    fn read(&mut self) -> Result<Option<CameraImage>, SlickscreenError> {
+        let error = |device: &str, e: ffmpeg_next::Error| {
+            SlickscreenError::CameraError(format!("{}: {}", device, e))
+        };
+
+        let mut frame = VideoFrame::empty();
+        loop {
+            if self.decoder.receive_frame(&mut frame).is_ok() {
+                return self
+                    .scale(&frame)
+                    .map(Some)
+                    .map_err(|e| error(&self.device, e));
+            }
+
+            let mut packet = ffmpeg_next::Packet::empty();
+            match packet.read(&mut self.input) {
+                Ok(()) => {}
+                Err(ffmpeg_next::Error::Other { errno }) if errno == EAGAIN => {
+                    std::thread::sleep(CAMERA_POLL_INTERVAL);
+                    return Ok(None);
+                }
+                Err(e) => return Err(error(&self.device, e)),
+            }
+            if packet.stream() == self.stream_index {
+                self.decoder
+                    .send_packet(&packet)
+                    .map_err(|e| error(&self.device, e))?;
+            }
+        }
+    }
+}
+
+/// Moving color bars at about 30 frames per second
+struct SyntheticCamera {
+    width: usize,
+    height: usize,
+    frame_index: usize,
+    next_frame: std::time::Instant,
+}
+
+impl SyntheticCamera {
+    fn new(width: u32) -> Self {
+        Self {
+            width: width as usize,
+            height: scaled_height(width, 16, 9) as usize,
+            frame_index: 0,
+            next_frame: std::time::Instant::now(),
+        }
+    }
+}
+
+impl CameraSource for SyntheticCamera {
+    fn read(&mut self) -> Result<Option<CameraImage>, SlickscreenError> {
+        const BARS: [[u8; 4]; 7] = [
+            [192, 192, 192, 255],
+            [0, 192, 192, 255],
+            [192, 192, 0, 255],
+            [0, 192, 0, 255],
+            [192, 0, 192, 255],
+            [0, 0, 192, 255],
+            [192, 0, 0, 255],
+        ];
+
+        let now = std::time::Instant::now();
+        if self.next_frame > now {
+            std::thread::sleep(self.next_frame - now);
+        }
+        self.next_frame += SYNTHETIC_CAMERA_INTERVAL;
+        self.frame_index += 1;
+
+        let bar_width = (self.width / BARS.len()).max(1);
+        let mut data = Vec::with_capacity(self.width * self.height * 4);
+        for _ in 0..self.height {
+            for column in 0..self.width {
+                let bar = (column + self.frame_index * 2) / bar_width % BARS.len();
+                data.extend_from_slice(&BARS[bar]);
+            }
+        }
+        Ok(Some(CameraImage {
+            width: self.width,
+            height: self.height,
+            data,
+        }))
+    }
+}
+
+/// Coverage of the pixel at `x`, `y` by a `width` by `height` rectangle whose corners are
+/// rounded to `radius`, antialiased over about one pixel
+fn rounded_rect_coverage(x: i64, y: i64, width: i64, height: i64, radius: f32) -> u8 {
+    if x < 0 || y < 0 || x >= width || y >= height {
+        return 0;
+    }
+    let radius = radius.min(width.min(height) as f32 / 2.0);
+    let (center_x, center_y) = (x as f32 + 0.5, y as f32 + 0.5);
+    // Distance from the circle of the nearest corner, zero outside the corners
+    let dx = center_x - center_x.clamp(radius, width as f32 - radius);
+    let dy = center_y - center_y.clamp(radius, height as f32 - radius);
+    let distance = (dx * dx + dy * dy).sqrt();
+    if distance == 0.0 {
+        return 255;
+    }
+    ((radius - distance + 0.5).clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// Draws the latest camera picture, which is read and decoded on its own thread so a slow
+/// camera never holds up the screen capture
+pub(crate) struct CameraOverlay {
+    latest: Arc<Mutex<Option<CameraImage>>>,
+    image: Option<CameraImage>,
+    position: OverlayPosition,
+    border: i64,
+    corner_radius: f32,
+    stop: Arc<AtomicBool>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl CameraOverlay {
+    /// Fails if the camera can not be opened
+    pub fn new(config: &CameraConfig) -> Result<Self, SlickscreenError> {
+        let latest = Arc::new(Mutex::new(None));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (opened_sender, opened_receiver) = crossbeam::channel::bounded(1);
+
+        // Scaling contexts can not move between threads, so the source is opened on the
+        // thread that reads it
+        let device = config.device.clone();
+        let width = config.width.max(2) & !1;
+        let thread_latest = latest.clone();
+        let thread_stop = stop.clone();
+        let thread = std::thread::spawn(move || {
+            let source: Result<Box<dyn CameraSource>, SlickscreenError> = match &device {
+                CameraDevice::V4l2(device) => V4l2Camera::open(device, width)
+                    .map(|camera| Box::new(camera) as Box<dyn CameraSource>),
+                CameraDevice::Synthetic => Ok(Box::new(SyntheticCamera::new(width))),
+            };
+            let mut source = match source {
+                Ok(source) => {
+                    let _ = opened_sender.send(Ok(()));
+                    source
+                }
+                Err(e) => {
+                    let _ = opened_sender.send(Err(e));
+                    return;
+                }
+            };
+
+            while !thread_stop.load(Ordering::Relaxed) {
+                match source.read() {
+                    Ok(Some(image)) => *thread_latest.lock().unwrap() = Some(image),
+                    Ok(None) => {}
+                    Err(e) => {
+                        println!("Recording without the camera - {}", e);
+                        return;
+                    }
+                }
+            }
+        });
+        opened_receiver.recv().unwrap_or_else(|_| {
+            Err(SlickscreenError::CameraError(
+                "camera thread exited".to_string(),
+            ))
+        })?;
+
+        Ok(Self {
+            latest,
+            image: None,
+            position: config.position,
+            border: config.border as i64,
+            corner_radius: config.corner_radius as f32,
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Overlay for CameraOverlay {
+    fn draw(&mut self, image: &mut BgraImage, _pts: i64) {
+        if let Some(latest) = self.latest.lock().unwrap().take() {
+            self.image = Some(latest);
+        }
+        // The last picture stays up if the camera is slower than the capture
+        let camera = match &self.image {
+            Some(camera) => camera,
+            None => return,
+        };
+
+        let (width, height) = (camera.width as i64, camera.height as i64);
+        let border = self.border;
+        let (outer_width, outer_height) = (width + border * 2, height + border * 2);
+        let (x, y) = self.position.place(
+            outer_width,
+            outer_height,
+            image.width() as i64,
+            image.height() as i64,
+            CAMERA_MARGIN,
+        );
+        let inner_radius = (self.corner_radius - border as f32).max(0.0);
+
+        for row in 0..outer_height {
+            for column in 0..outer_width {
+                let inner = rounded_rect_coverage(
+                    column - border,
+                    row - border,
+                    width,
+                    height,
+                    inner_radius,
+                );
+                if inner < 255 && border > 0 {
+                    let outer = rounded_rect_coverage(
+                        column,
+                        row,
+                        outer_width,
+                        outer_height,
+                        self.corner_radius,
+                    );
+                    if outer > 0 {
+                        let [b, g, r, a] = CAMERA_BORDER_COLOR;
+                        let alpha = (a as u32 * outer as u32 / 255) as u8;
+                        image.blend(x + column, y + row, [b, g, r, alpha]);
+                    }
+                }
+                if inner > 0 {
+                    let offset = (((row - border) * width + column - border) * 4) as usize;
+                    let pixel = &camera.data[offset..offset + 4];
+                    image.blend(x + column, y + row, [pixel[0], pixel[1], pixel[2], inner]);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for CameraOverlay {
+    /// Sources never block for long, so the thread notices the stop flag quickly
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scaled_height_keeps_aspect_ratio_and_is_even() {
+        assert_eq!(scaled_height(320, 1920, 1080), 180);
+        assert_eq!(scaled_height(320, 640, 480), 240);
+        // 55.7 rounds down to an even height
+        assert_eq!(scaled_height(99, 16, 9), 54);
+        assert_eq!(scaled_height(10, 1000, 1), 2);
+    }
+
+    #[test]
+    fn rounded_rect_coverage_rounds_corners() {
+        assert_eq!(rounded_rect_coverage(-1, 5, 20, 20, 8.0), 0);
+        assert_eq!(rounded_rect_coverage(5, 20, 20, 20, 8.0), 0);
+        assert_eq!(rounded_rect_coverage(10, 10, 20, 20, 8.0), 255);
+        // The corner pixel lies outside the rounded corner, the border between corners is
+        // fully covered
+        assert_eq!(rounded_rect_coverage(0, 0, 20, 20, 8.0), 0);
+        assert_eq!(rounded_rect_coverage(19, 19, 20, 20, 8.0), 0);
+        assert_eq!(rounded_rect_coverage(0, 10, 20, 20, 8.0), 255);
+        assert_eq!(rounded_rect_coverage(10, 19, 20, 20, 8.0), 255);
+        // Square corners
+        assert_eq!(rounded_rect_coverage(0, 0, 20, 20, 0.0), 255);
+    }
+
+    #[test]
+    fn rounded_rect_coverage_limits_radius_to_half_the_size() {
+        // A radius of 100 turns a 10 by 10 square into a circle
+        for (x, y) in [(0, 0), (9, 0), (0, 9), (9, 9)] {
+            assert_eq!(rounded_rect_coverage(x, y, 10, 10, 100.0), 0);
+        }
+        assert_eq!(rounded_rect_coverage(4, 4, 10, 10, 100.0), 255);
+        let edge = rounded_rect_coverage(0, 4, 10, 10, 100.0);
+        assert!(edge > 0 && edge < 255, "{}", edge);
+        for x in 0..10 {
+            for y in 0..10 {
+                assert_eq!(
+                    rounded_rect_coverage(x, y, 10, 10, 100.0),
+                    rounded_rect_coverage(9 - x, 9 - y, 10, 10, 100.0)
+                );
+            }
+        }
+    }
+
+    const WIDTH: usize = 200;
+    const HEIGHT: usize = 150;
+
+    fn synthetic_overlay(border: u32, corner_radius: u32) -> CameraOverlay {
+        let image = SyntheticCamera::new(64).read().unwrap();
+        CameraOverlay {
+            latest: Arc::new(Mutex::new(image)),
+            image: None,
+            position: OverlayPosition::TopLeft,
+            border: border as i64,
+            corner_radius: corner_radius as f32,
+            stop: Arc::new(AtomicBool::new(false)),
+            thread: None,
+        }
+    }
+
+    fn pixel(data: &[u8], x: usize, y: usize) -> [u8; 4] {
+        let offset = (y * WIDTH + x) * 4;
+        data[offset..offset + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn camera_is_drawn_inside_its_border() {
+        let mut overlay = synthetic_overlay(4, 0);
+        let mut data = vec![0; WIDTH * HEIGHT * 4];
+        overlay.draw(&mut BgraImage::new(&mut data, WIDTH * 4, WIDTH, HEIGHT), 0);
+
+        // The 64 by 36 picture with a 4 pixel border, the margin away from the corner
+        let (left, top) = (CAMERA_MARGIN as usize, CAMERA_MARGIN as usize);
+        let (right, bottom) = (left + 64 + 8 - 1, top + 36 + 8 - 1);
+        assert_eq!(pixel(&data, left - 1, top), [0; 4]);
+        assert_eq!(pixel(&data, left, top - 1), [0; 4]);
+        assert_eq!(pixel(&data, right + 1, bottom), [0; 4]);
+        assert_eq!(pixel(&data, right, bottom + 1), [0; 4]);
+        for (x, y) in [
+            (left, top),
+            (right, top),
+            (left + 3, top + 20),
+            (right, bottom),
+        ] {
+            assert_eq!(pixel(&data, x, y), CAMERA_BORDER_COLOR);
+        }
+
+        // The first frame of the bars is shifted by 2 pixels, bars are 9 pixels wide
+        assert_eq!(pixel(&data, left + 4, top + 4), [192, 192, 192, 255]);
+        assert_eq!(pixel(&data, left + 4 + 7, bottom - 4), [0, 192, 192, 255]);
+        assert_eq!(pixel(&data, right - 4, top + 4), [192, 192, 192, 255]);
+    }
+
+    #[test]
+    fn rounded_corners_leave_the_screen_visible() {
+        let mut overlay = synthetic_overlay(4, 16);
+        let mut data = vec![0; WIDTH * HEIGHT * 4];
+        overlay.draw(&mut BgraImage::new(&mut data, WIDTH * 4, WIDTH, HEIGHT), 0);
+
+        let (left, top) = (CAMERA_MARGIN as usize, CAMERA_MARGIN as usize);
+        assert_eq!(pixel(&data, left, top), [0; 4]);
+        assert_eq!(pixel(&data, left, top + 20), CAMERA_BORDER_COLOR);
+        assert_eq!(pixel(&data, left + 9, top + 20), [192, 192, 192, 255]);
+    }
+}
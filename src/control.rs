@@ -0,0 +1,227 @@
+use super::*;
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+#[derive(Clone, Debug)]
+pub struct PreviewConfig {
+    /// Width of the preview image, the height follows the display aspect ratio
+    pub width: u32,
+    /// Minimum time between two preview images
+    pub interval: Duration,
+}
+
+impl Default for PreviewConfig {
+    fn default() -> Self {
+        PreviewConfig {
+            width: 640,
+            interval: Duration::from_millis(200),
+        }
+    }
+}
+
+/// A labelled point in the recording, written as a chapter when the outputs are finalized
+#[derive(Clone, Debug, Serialize)]
+pub struct Marker {
+    /// Microseconds from the start of the recording, not counting pauses
+    pub timestamp_us: i64,
+    pub label: String,
+}
+
+pub(crate) type MarkerList = Arc<Mutex<Vec<Marker>>>;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SlickscreenState {
+    Recording,
+    Paused,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct OutputStatus {
+    pub url: String,
+    /// False once a network output has been dropped
+    pub connected: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SlickscreenStatus {
+    pub state: SlickscreenState,
+    /// Length of the recording so far, not counting pauses
+    pub duration_ms: u64,
+    pub outputs: Vec<OutputStatus>,
+    pub markers: Vec<Marker>,
+    pub masks: Vec<MaskRegion>,
+    pub stats: SlickscreenStats,
+}
+
+/// The most recent JPEG encoded preview of the capture
+#[derive(Default)]
+pub(crate) struct PreviewFrame {
+    latest: Mutex<(u64, Option<Arc<Vec<u8>>>)>,
+    updated: Condvar,
+}
+
+impl PreviewFrame {
+    pub fn publish(&self, jpeg: Vec<u8>) {
+        let mut latest = self.latest.lock().unwrap();
+        *latest = (latest.0 + 1, Some(Arc::new(jpeg)));
+        self.updated.notify_all();
+    }
+
+    fn latest(&self) -> Option<(u64, Arc<Vec<u8>>)> {
+        let latest = self.latest.lock().unwrap();
+        latest.1.clone().map(|jpeg| (latest.0, jpeg))
+    }
+
+    fn wait_newer(&self, sequence: u64, timeout: Duration) -> Option<(u64, Arc<Vec<u8>>)> {
+        let latest = self.latest.lock().unwrap();
+        let (latest, _) = self
+            .updated
+            .wait_timeout_while(latest, timeout, |latest| latest.0 <= sequence)
+            .unwrap();
+        match &latest.1 {
+            Some(jpeg) if latest.0 > sequence => Some((latest.0, jpeg.clone())),
+            _ => None,
+        }
+    }
+}
+
+/// A cheap, thread safe handle for driving a running recording from other threads
+#[derive(Clone)]
+pub struct SlickscreenControl {
+    time_reference: SlickscreenTime,
+    preview: Arc<PreviewFrame>,
+    outputs: Arc<Vec<(String, Arc<AtomicBool>)>>,
+    markers: MarkerList,
+    masks: MaskList,
+    stats: Arc<StatsCounters>,
+    keyframe_requested: Arc<AtomicBool>,
+}
+
+impl SlickscreenControl {
+    pub(crate) fn new(
+        time_reference: SlickscreenTime,
+        preview: Arc<PreviewFrame>,
+        outputs: Vec<(String, Arc<AtomicBool>)>,
+        markers: MarkerList,
+        masks: MaskList,
+        stats: Arc<StatsCounters>,
+        keyframe_requested: Arc<AtomicBool>,
+    ) -> Self {
+        Self {
+            time_reference,
+            preview,
+            outputs: Arc::new(outputs),
+            markers,
+            masks,
+            stats,
+            keyframe_requested,
+        }
+    }
+
+    /// Stop capturing until `resume` is called, returns false if already paused
+    pub fn pause(&self) -> bool {
+        self.time_reference.pause()
+    }
+
+    /// Returns false if the recording was not paused
+    pub fn resume(&self) -> bool {
+        self.time_reference.resume()
+    }
+
+    /// Mark the current point in the recording, the next frame becomes a keyframe so
+    /// players can seek straight to the marker
+    pub fn add_marker(&self, label: &str) -> Marker {
+        self.request_keyframe();
+        let marker = Marker {
+            timestamp_us: self.time_reference.pts_now(),
+            label: label.to_string(),
+        };
+        self.markers.lock().unwrap().push(marker.clone());
+        marker
+    }
+
+    /// Encode the next video frame as a keyframe, e.g. when a viewer joins a stream
+    pub fn request_keyframe(&self) {
+        // Requests made before the next frame is captured are one keyframe
+        self.keyframe_requested.store(true, Ordering::Relaxed);
+    }
+
+    /// Hide `region` from the recording from the next frame on, replacing the mask of the
+    /// same name. Returns the mask with its name filled in
+    pub fn set_mask(&self, region: MaskRegion) -> MaskRegion {
+        insert_mask(&mut self.masks.lock().unwrap(), region)
+    }
+
+    /// Returns false if there is no mask named `name`
+    pub fn remove_mask(&self, name: &str) -> bool {
+        let mut masks = self.masks.lock().unwrap();
+        let count = masks.len();
+        masks.retain(|mask| mask.name != name);
+        masks.len() != count
+    }
+
+    /// Turn a mask off without forgetting it, returns false if there is no mask named `name`
+    pub fn set_mask_enabled(&self, name: &str, enabled: bool) -> bool {
+        match self
+            .masks
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .find(|mask| mask.name == name)
+        {
+            Some(mask) => {
+                mask.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn masks(&self) -> Vec<MaskRegion> {
+        self.masks.lock().unwrap().clone()
+    }
+
+    pub fn status(&self) -> SlickscreenStatus {
+        SlickscreenStatus {
+            state: if self.time_reference.is_paused() {
+                SlickscreenState::Paused
+            } else {
+                SlickscreenState::Recording
+            },
+            duration_ms: (self.time_reference.pts_now() / 1000) as u64,
+            outputs: self
+                .outputs
+                .iter()
+                .map(|(url, failed)| OutputStatus {
+                    url: url.clone(),
+                    connected: !failed.load(Ordering::SeqCst),
+                })
+                .collect(),
+            markers: self.markers.lock().unwrap().clone(),
+            masks: self.masks(),
+            stats: self.stats(),
+        }
+    }
+
+    pub fn stats(&self) -> SlickscreenStats {
+        self.stats.snapshot()
+    }
+
+    /// The latest preview image and its sequence number, if previews are enabled
+    pub fn preview_jpeg(&self) -> Option<(u64, Arc<Vec<u8>>)> {
+        self.preview.latest()
+    }
+
+    /// Wait up to `timeout` for a preview image newer than `sequence`
+    pub fn next_preview_jpeg(
+        &self,
+        sequence: u64,
+        timeout: Duration,
+    ) -> Option<(u64, Arc<Vec<u8>>)> {
+        self.preview.wait_newer(sequence, timeout)
+    }
+}
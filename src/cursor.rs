@@ -0,0 +1,390 @@
+use super::*;
+
+use crate::overlay::{BgraImage, Overlay};
+
+/// How the mouse cursor appears in the recording
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CursorMode {
+    /// Leave the cursor out, as the screen capture does by itself
+    Hide,
+    Show,
+    /// Draw the cursor at twice its size so it is easy to follow in tutorials
+    Enlarge,
+}
+
+impl Default for CursorMode {
+    fn default() -> Self {
+        CursorMode::Show
+    }
+}
+
+impl std::str::FromStr for CursorMode {
+    type Err = SlickscreenError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hide" => Ok(CursorMode::Hide),
+            "show" => Ok(CursorMode::Show),
+            "enlarge" => Ok(CursorMode::Enlarge),
+            _ => Err(SlickscreenError::CursorError(format!(
+                "unknown cursor mode {}, expected hide, show or enlarge",
+                s
+            ))),
+        }
+    }
+}
+
+const ENLARGED_CURSOR_SCALE: usize = 2;
+
+/// A cursor image with premultiplied BGRA pixels
+struct CursorImage {
+    width: usize,
+    height: usize,
+    hot_x: usize,
+    hot_y: usize,
+    pixels: Vec<[u8; 4]>,
+}
+
+impl CursorImage {
+    /// Nearest neighbour scaling, cursors are small and blocky enough for it to look right
+    fn scaled(self, scale: usize) -> Self {
+        if scale == 1 {
+            return self;
+        }
+        let (width, height) = (self.width * scale, self.height * scale);
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                pixels.push(self.pixels[(y / scale) * self.width + x / scale]);
+            }
+        }
+        Self {
+            width,
+            height,
+            hot_x: self.hot_x * scale,
+            hot_y: self.hot_y * scale,
+            pixels,
+        }
+    }
+
+    /// Blend the cursor with its hotspot at `x`, `y`
+    fn draw(&self, frame: &mut BgraImage, x: i64, y: i64) {
+        let left = x - self.hot_x as i64;
+        let top = y - self.hot_y as i64;
+        for row in 0..self.height {
+            for column in 0..self.width {
+                frame.blend_premultiplied(
+                    left + column as i64,
+                    top + row as i64,
+                    self.pixels[row * self.width + column],
+                );
+            }
+        }
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod xfixes {
+    use super::*;
+
+    use std::rc::Rc;
+
+    use x11::{xfixes, xlib};
+
+    // Not exported by the x11 crate, from Xfixes.h
+    const XFIXES_CURSOR_NOTIFY: i32 = 1;
+    const XFIXES_DISPLAY_CURSOR_NOTIFY_MASK: std::os::raw::c_ulong = 1;
+
+    // The x11 crate declares the display argument by value, which cannot be called
+    extern "C" {
+        fn XFixesSelectCursorInput(
+            display: *mut xlib::Display,
+            window: xlib::Window,
+            event_mask: std::os::raw::c_ulong,
+        );
+    }
+
+    /// The origin of the monitor `scrap::Display::primary` captures, picked the same way
+    fn primary_monitor_origin() -> (i64, i64) {
+        let server = match scrap::x11::Server::default() {
+            Ok(server) => Rc::new(server),
+            Err(_) => return (0, 0),
+        };
+        let mut displays = scrap::x11::Server::displays(server);
+        let mut best = displays.next();
+        if best.as_ref().map(|display| display.is_default()) == Some(false) {
+            best = displays.find(|display| display.is_default()).or(best);
+        }
+        best.map_or((0, 0), |display| {
+            (display.rect().x as i64, display.rect().y as i64)
+        })
+    }
+
+    /// Follows the cursor with the XFixes extension, the image is only read again after
+    /// the X server notifies a cursor change
+    pub(super) struct CursorSource {
+        display: *mut xlib::Display,
+        root: xlib::Window,
+        event_base: i32,
+        origin: (i64, i64),
+        image_changed: bool,
+    }
+
+    // The display connection is only ever used by the thread that owns the source
+    unsafe impl Send for CursorSource {}
+
+    impl CursorSource {
+        pub fn open() -> Result<Self, SlickscreenError> {
+            let origin = primary_monitor_origin();
+            unsafe {
+                let display = xlib::XOpenDisplay(std::ptr::null());
+                if display.is_null() {
+                    return Err(SlickscreenError::CursorError(
+                        "unable to connect to the X server".to_string(),
+                    ));
+                }
+                let (mut event_base, mut error_base) = (0, 0);
+                if xfixes::XFixesQueryExtension(display, &mut event_base, &mut error_base) == 0 {
+                    xlib::XCloseDisplay(display);
+                    return Err(SlickscreenError::CursorError(
+                        "the X server does not support XFixes".to_string(),
+                    ));
+                }
+                let root = xlib::XDefaultRootWindow(display);
+                XFixesSelectCursorInput(display, root, XFIXES_DISPLAY_CURSOR_NOTIFY_MASK);
+                xlib::XFlush(display);
+                Ok(Self {
+                    display,
+                    root,
+                    event_base,
+                    origin,
+                    image_changed: true,
+                })
+            }
+        }
+
+        /// The cursor position on the captured monitor and, if it changed since the last
+        /// call, its image
+        pub fn cursor(&mut self) -> Option<(i64, i64, Option<CursorImage>)> {
+            unsafe {
+                let mut event: xlib::XEvent = std::mem::zeroed();
+                while xlib::XCheckTypedEvent(
+                    self.display,
+                    self.event_base + XFIXES_CURSOR_NOTIFY,
+                    &mut event,
+                ) != 0
+                {
+                    self.image_changed = true;
+                }
+
+                let (mut root, mut child) = (0, 0);
+                let (mut x, mut y, mut window_x, mut window_y, mut mask) = (0, 0, 0, 0, 0);
+                if xlib::XQueryPointer(
+                    self.display,
+                    self.root,
+                    &mut root,
+                    &mut child,
+                    &mut x,
+                    &mut y,
+                    &mut window_x,
+                    &mut window_y,
+                    &mut mask,
+                ) == 0
+                {
+                    // The pointer is on another screen
+                    return None;
+                }
+                let (x, y) = (x as i64 - self.origin.0, y as i64 - self.origin.1);
+
+                let cursor_image = if self.image_changed {
+                    let image = self.image()?;
+                    self.image_changed = false;
+                    Some(image)
+                } else {
+                    None
+                };
+                Some((x, y, cursor_image))
+            }
+        }
+
+        unsafe fn image(&mut self) -> Option<CursorImage> {
+            let image = xfixes::XFixesGetCursorImage(self.display);
+            if image.is_null() {
+                return None;
+            }
+            let (width, height) = ((*image).width as usize, (*image).height as usize);
+            // Each pixel is premultiplied ARGB in the low 32 bits of a long
+            let pixels = std::slice::from_raw_parts((*image).pixels, width * height)
+                .iter()
+                .map(|&argb| {
+                    let argb = argb as u32;
+                    [
+                        argb as u8,
+                        (argb >> 8) as u8,
+                        (argb >> 16) as u8,
+                        (argb >> 24) as u8,
+                    ]
+                })
+                .collect();
+            let cursor_image = CursorImage {
+                width,
+                height,
+                hot_x: (*image).xhot as usize,
+                hot_y: (*image).yhot as usize,
+                pixels,
+            };
+            xlib::XFree(image as *mut std::ffi::c_void);
+            Some(cursor_image)
+        }
+    }
+
+    impl Drop for CursorSource {
+        fn drop(&mut self) {
+            unsafe {
+                xlib::XCloseDisplay(self.display);
+            }
+        }
+    }
+}
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+mod xfixes {
+    use super::*;
+
+    pub(super) struct CursorSource;
+
+    impl CursorSource {
+        pub fn open() -> Result<Self, SlickscreenError> {
+            Err(SlickscreenError::CursorError(
+                "cursor capture is only supported on X11".to_string(),
+            ))
+        }
+
+        pub fn cursor(&mut self) -> Option<(i64, i64, Option<CursorImage>)> {
+            None
+        }
+    }
+}
+
+/// Draws the mouse cursor, which the screen capture leaves out
+pub(crate) struct CursorOverlay {
+    source: xfixes::CursorSource,
+    scale: usize,
+    image: Option<CursorImage>,
+}
+
+impl CursorOverlay {
+    /// Returns None for `CursorMode::Hide`
+    pub fn new(mode: CursorMode) -> Result<Option<Self>, SlickscreenError> {
+        let scale = match mode {
+            CursorMode::Hide => return Ok(None),
+            CursorMode::Show => 1,
+            CursorMode::Enlarge => ENLARGED_CURSOR_SCALE,
+        };
+        Ok(Some(Self {
+            source: xfixes::CursorSource::open()?,
+            scale,
+            image: None,
+        }))
+    }
+}
+
+impl Overlay for CursorOverlay {
+    fn draw(&mut self, frame: &mut BgraImage, _pts: i64) {
+        let (x, y) = match self.source.cursor() {
+            Some((x, y, Some(image))) => {
+                self.image = Some(image.scaled(self.scale));
+                (x, y)
+            }
+            Some((x, y, None)) => (x, y),
+            None => return,
+        };
+        if let Some(image) = &self.image {
+            image.draw(frame, x, y);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 16;
+    const HEIGHT: usize = 16;
+
+    const RED: [u8; 4] = [0, 0, 255, 255];
+    const GREEN: [u8; 4] = [0, 255, 0, 255];
+    const BLUE: [u8; 4] = [255, 0, 0, 255];
+    const CLEAR: [u8; 4] = [0; 4];
+
+    /// A 2 by 2 cursor with its hotspot on the bottom right pixel
+    fn synthetic_cursor() -> CursorImage {
+        CursorImage {
+            width: 2,
+            height: 2,
+            hot_x: 1,
+            hot_y: 1,
+            pixels: vec![RED, GREEN, BLUE, CLEAR],
+        }
+    }
+
+    fn draw(image: &CursorImage, x: i64, y: i64) -> Vec<u8> {
+        let mut data = vec![0; WIDTH * HEIGHT * 4];
+        image.draw(
+            &mut BgraImage::new(&mut data, WIDTH * 4, WIDTH, HEIGHT),
+            x,
+            y,
+        );
+        data
+    }
+
+    fn pixel(data: &[u8], x: usize, y: usize) -> [u8; 4] {
+        let offset = (y * WIDTH + x) * 4;
+        data[offset..offset + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn enlarged_cursor_repeats_pixels_and_scales_the_hotspot() {
+        let image = synthetic_cursor().scaled(ENLARGED_CURSOR_SCALE);
+        assert_eq!((image.width, image.height), (4, 4));
+        assert_eq!((image.hot_x, image.hot_y), (2, 2));
+        #[rustfmt::skip]
+        let expected = vec![
+            RED, RED, GREEN, GREEN,
+            RED, RED, GREEN, GREEN,
+            BLUE, BLUE, CLEAR, CLEAR,
+            BLUE, BLUE, CLEAR, CLEAR,
+        ];
+        assert_eq!(image.pixels, expected);
+    }
+
+    #[test]
+    fn cursor_hotspot_lands_on_the_pointer_position() {
+        let data = draw(&synthetic_cursor(), 5, 7);
+        assert_eq!(pixel(&data, 4, 6), RED);
+        assert_eq!(pixel(&data, 5, 6), GREEN);
+        assert_eq!(pixel(&data, 4, 7), BLUE);
+        assert_eq!(pixel(&data, 5, 7), CLEAR);
+        assert_eq!(pixel(&data, 3, 6), CLEAR);
+        assert_eq!(pixel(&data, 4, 5), CLEAR);
+
+        let data = draw(&synthetic_cursor().scaled(ENLARGED_CURSOR_SCALE), 5, 7);
+        assert_eq!(pixel(&data, 3, 5), RED);
+        assert_eq!(pixel(&data, 4, 6), RED);
+        assert_eq!(pixel(&data, 5, 5), GREEN);
+        assert_eq!(pixel(&data, 3, 7), BLUE);
+        assert_eq!(pixel(&data, 5, 7), CLEAR);
+        assert_eq!(pixel(&data, 2, 5), CLEAR);
+        assert_eq!(pixel(&data, 3, 4), CLEAR);
+    }
+
+    #[test]
+    fn cursor_is_clipped_at_the_frame_edges() {
+        let data = draw(&synthetic_cursor(), 0, 0);
+        assert_eq!(pixel(&data, 0, 0), CLEAR);
+        assert!(data.iter().all(|&byte| byte == 0));
+
+        let data = draw(&synthetic_cursor(), WIDTH as i64, HEIGHT as i64);
+        assert_eq!(pixel(&data, WIDTH - 1, HEIGHT - 1), RED);
+    }
+}
@@ -0,0 +1,387 @@
+use super::*;
+
+use std::ffi::CString;
+
+/// Named sets of encoder options, tuned for a particular use of the recording
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EncoderProfile {
+    /// Slow, high quality encoding for keeping recordings around
+    Archive,
+    /// Smaller files for uploading and sharing
+    Share,
+    /// Fast, low latency encoding for live streaming
+    Stream,
+    /// Mathematically lossless, produces very large files
+    Lossless,
+}
+
+impl Default for EncoderProfile {
+    fn default() -> Self {
+        EncoderProfile::Stream
+    }
+}
+
+impl std::str::FromStr for EncoderProfile {
+    type Err = SlickscreenError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "archive" => Ok(EncoderProfile::Archive),
+            "share" => Ok(EncoderProfile::Share),
+            "stream" => Ok(EncoderProfile::Stream),
+            "lossless" => Ok(EncoderProfile::Lossless),
+            _ => Err(SlickscreenError::InvalidEncoderConfig(format!(
+                "unknown profile {}, expected archive, share, stream or lossless",
+                s
+            ))),
+        }
+    }
+}
+
+impl EncoderProfile {
+    /// Rate control used when none is configured explicitly
+    pub fn default_rate_control(&self) -> RateControl {
+        match self {
+            EncoderProfile::Archive => RateControl::Crf(12),
+            EncoderProfile::Share => RateControl::Crf(22),
+            EncoderProfile::Stream => RateControl::Crf(15),
+            EncoderProfile::Lossless => RateControl::ConstantQp(0),
+        }
+    }
+
+    /// Options for `encoder_name`, encoders without a tuned profile get none
+    pub(crate) fn apply(
+        &self,
+        encoder_name: &str,
+        chroma_format: ChromaFormat,
+        options: &mut ffmpeg_next::Dictionary,
+    ) {
+        if encoder_name != "libx264" && encoder_name != "libx264rgb" {
+            return;
+        }
+
+        let profile_options: &[(&str, &str)] = match self {
+            EncoderProfile::Archive => &[
+                ("preset", "slow"),
+                ("tune", "stillimage"),
+                ("profile", "high"),
+                ("refs", "4"),
+            ],
+            EncoderProfile::Share => &[
+                ("preset", "slower"),
+                ("profile", "high"),
+                ("level", "4.2"),
+                ("refs", "3"),
+            ],
+            EncoderProfile::Stream => &[
+                ("preset", "medium"),
+                ("tune", "zerolatency"),
+                ("level", "4.2"),
+                ("profile", "high"),
+                ("refs", "1"),
+                ("qdiff", "4"),
+                ("qcompress", "0.6"),
+            ],
+            // Lossless coding needs the High 4:4:4 Predictive profile
+            EncoderProfile::Lossless => &[("preset", "ultrafast"), ("profile", "high444")],
+        };
+        for (key, value) in profile_options {
+            options.set(key, value);
+        }
+        if chroma_format != ChromaFormat::Yuv420 {
+            // Full resolution chroma is only allowed by the High 4:4:4 Predictive profile
+            options.set("profile", "high444");
+        }
+    }
+}
+
+/// How the color of each pixel is stored by the video encoder
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChromaFormat {
+    /// Chroma at quarter resolution with x264, plays everywhere but smears colored text
+    Yuv420,
+    /// Chroma at full resolution with x264 High 4:4:4, keeps text and UI edges crisp
+    Yuv444,
+    /// RGB without color conversion with x264rgb
+    Rgb,
+    /// Planar RGB with the lossless FFV1 codec, needs a container such as MKV
+    Gbrp,
+}
+
+impl Default for ChromaFormat {
+    fn default() -> Self {
+        ChromaFormat::Yuv420
+    }
+}
+
+impl std::str::FromStr for ChromaFormat {
+    type Err = SlickscreenError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "yuv420" | "420" => Ok(ChromaFormat::Yuv420),
+            "yuv444" | "444" => Ok(ChromaFormat::Yuv444),
+            "rgb" => Ok(ChromaFormat::Rgb),
+            "gbrp" | "ffv1" => Ok(ChromaFormat::Gbrp),
+            _ => Err(SlickscreenError::InvalidEncoderConfig(format!(
+                "unknown chroma format {}, expected yuv420, yuv444, rgb or gbrp",
+                s
+            ))),
+        }
+    }
+}
+
+impl ChromaFormat {
+    pub(crate) fn encoder_name(&self) -> &'static str {
+        match self {
+            ChromaFormat::Yuv420 | ChromaFormat::Yuv444 => "libx264",
+            ChromaFormat::Rgb => "libx264rgb",
+            ChromaFormat::Gbrp => "ffv1",
+        }
+    }
+
+    pub(crate) fn pixel_format(&self) -> ffmpeg_next::util::format::Pixel {
+        use ffmpeg_next::util::format::Pixel;
+        match self {
+            ChromaFormat::Yuv420 => Pixel::YUV420P,
+            ChromaFormat::Yuv444 => Pixel::YUV444P,
+            ChromaFormat::Rgb => Pixel::BGRZ,
+            ChromaFormat::Gbrp => Pixel::GBRP,
+        }
+    }
+
+    pub(crate) fn is_rgb(&self) -> bool {
+        matches!(self, ChromaFormat::Rgb | ChromaFormat::Gbrp)
+    }
+}
+
+/// The range of YUV sample values used by the encoded video
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorRange {
+    /// 16-235 for luma and 16-240 for chroma, what players assume when unsure
+    Limited,
+    /// The full 0-255 range, keeps every shade of the captured RGB but some players
+    /// ignore the signalling and show washed out or crushed colors
+    Full,
+}
+
+impl Default for ColorRange {
+    fn default() -> Self {
+        ColorRange::Limited
+    }
+}
+
+impl std::str::FromStr for ColorRange {
+    type Err = SlickscreenError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "limited" | "tv" => Ok(ColorRange::Limited),
+            "full" | "pc" => Ok(ColorRange::Full),
+            _ => Err(SlickscreenError::InvalidEncoderConfig(format!(
+                "unknown color range {}, expected limited or full",
+                s
+            ))),
+        }
+    }
+}
+
+impl ColorRange {
+    pub(crate) fn is_full(&self) -> bool {
+        *self == ColorRange::Full
+    }
+
+    pub(crate) fn to_ffmpeg(self) -> ffmpeg_next::util::color::Range {
+        match self {
+            ColorRange::Limited => ffmpeg_next::util::color::Range::MPEG,
+            ColorRange::Full => ffmpeg_next::util::color::Range::JPEG,
+        }
+    }
+}
+
+/// How the video encoder spreads its work over threads
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EncoderThreading {
+    /// Encode several frames at once, the best throughput but each thread adds a frame
+    /// of latency
+    Frame,
+    /// Split every frame into slices encoded in parallel, no added latency but slightly
+    /// worse compression
+    Slice,
+}
+
+impl Default for EncoderThreading {
+    fn default() -> Self {
+        EncoderThreading::Frame
+    }
+}
+
+impl std::str::FromStr for EncoderThreading {
+    type Err = SlickscreenError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "frame" => Ok(EncoderThreading::Frame),
+            "slice" => Ok(EncoderThreading::Slice),
+            _ => Err(SlickscreenError::InvalidEncoderConfig(format!(
+                "unknown threading {}, expected frame or slice",
+                s
+            ))),
+        }
+    }
+}
+
+/// Check every key against the AVOptions of the codec context and the encoder's private
+/// options, returning an error that lists all unknown keys
+pub(crate) fn validate_encoder_options(
+    codec: &ffmpeg_next::Codec,
+    options: &[(String, String)],
+) -> Result<(), SlickscreenError> {
+    use ffmpeg_next::ffi::{av_opt_find, avcodec_get_class, AVClass, AV_OPT_SEARCH_FAKE_OBJ};
+
+    let mut unknown = Vec::new();
+    for (key, _) in options {
+        let name = CString::new(key.as_str())
+            .map_err(|_e| SlickscreenError::InvalidEncoderConfig(key.clone()))?;
+        let found = unsafe {
+            let classes: [*const AVClass; 2] = [avcodec_get_class(), (*codec.as_ptr()).priv_class];
+            classes.iter().any(|class| {
+                !class.is_null()
+                    && !av_opt_find(
+                        class as *const *const AVClass as *mut std::ffi::c_void,
+                        name.as_ptr(),
+                        std::ptr::null(),
+                        0,
+                        AV_OPT_SEARCH_FAKE_OBJ as i32,
+                    )
+                    .is_null()
+            })
+        };
+        if !found {
+            unknown.push(key.as_str());
+        }
+    }
+
+    if unknown.is_empty() {
+        Ok(())
+    } else {
+        Err(SlickscreenError::InvalidEncoderConfig(format!(
+            "unknown options for {}: {}",
+            codec.name(),
+            unknown.join(", ")
+        )))
+    }
+}
+
+/// How the video encoder trades quality against bitrate
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RateControl {
+    /// Constant quality, lower is better. x264 and x265 use 0-51, VP9 uses 0-63
+    Crf(u32),
+    /// The same quantizer for every frame, 0 is lossless with x264
+    ConstantQp(u32),
+    /// Average bitrate in bits per second
+    Abr { bitrate: u64 },
+    /// Constant bitrate in bits per second, `vbv_buffer_size` in bits bounds how far the
+    /// short term rate may deviate from it
+    Cbr { bitrate: u64, vbv_buffer_size: u64 },
+}
+
+impl RateControl {
+    /// Translate the rate control mode into options understood by `encoder_name`
+    pub(crate) fn apply(
+        &self,
+        encoder_name: &str,
+        options: &mut ffmpeg_next::Dictionary,
+    ) -> Result<(), SlickscreenError> {
+        match encoder_name {
+            "libx264" | "libx264rgb" | "libx265" => match *self {
+                RateControl::Crf(crf) => {
+                    options.set("crf", &crf.to_string());
+                    options.set("qmin", "10");
+                    options.set("qmax", "51");
+                }
+                RateControl::ConstantQp(qp) => {
+                    options.set("qp", &qp.to_string());
+                }
+                RateControl::Abr { bitrate } => {
+                    options.set("b", &bitrate.to_string());
+                }
+                RateControl::Cbr {
+                    bitrate,
+                    vbv_buffer_size,
+                } => {
+                    Self::set_cbr(options, bitrate, vbv_buffer_size);
+                    if encoder_name != "libx265" {
+                        // Pad the stream so the rate is constant at the transport level
+                        options.set("nal-hrd", "cbr");
+                    }
+                }
+            },
+            "libvpx" | "libvpx-vp9" => match *self {
+                RateControl::Crf(crf) => {
+                    // A zero bitrate turns libvpx's constrained quality into constant quality
+                    options.set("crf", &crf.to_string());
+                    options.set("b", "0");
+                }
+                RateControl::ConstantQp(qp) => {
+                    options.set("qmin", &qp.to_string());
+                    options.set("qmax", &qp.to_string());
+                    options.set("b", "0");
+                }
+                RateControl::Abr { bitrate } => {
+                    options.set("b", &bitrate.to_string());
+                }
+                RateControl::Cbr {
+                    bitrate,
+                    vbv_buffer_size,
+                } => Self::set_cbr(options, bitrate, vbv_buffer_size),
+            },
+            name if name.ends_with("_nvenc") => match *self {
+                RateControl::Crf(crf) => {
+                    options.set("rc", "vbr");
+                    options.set("cq", &crf.to_string());
+                    options.set("b", "0");
+                }
+                RateControl::ConstantQp(qp) => {
+                    options.set("rc", "constqp");
+                    options.set("qp", &qp.to_string());
+                }
+                RateControl::Abr { bitrate } => {
+                    options.set("rc", "vbr");
+                    options.set("b", &bitrate.to_string());
+                }
+                RateControl::Cbr {
+                    bitrate,
+                    vbv_buffer_size,
+                } => {
+                    options.set("rc", "cbr");
+                    Self::set_cbr(options, bitrate, vbv_buffer_size);
+                }
+            },
+            name => match *self {
+                RateControl::Abr { bitrate } => {
+                    options.set("b", &bitrate.to_string());
+                }
+                RateControl::Cbr {
+                    bitrate,
+                    vbv_buffer_size,
+                } => Self::set_cbr(options, bitrate, vbv_buffer_size),
+                RateControl::Crf(_) | RateControl::ConstantQp(_) => {
+                    return Err(SlickscreenError::InvalidEncoderConfig(format!(
+                        "{:?} is not supported by {}",
+                        self, name
+                    )));
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn set_cbr(options: &mut ffmpeg_next::Dictionary, bitrate: u64, vbv_buffer_size: u64) {
+        options.set("b", &bitrate.to_string());
+        options.set("minrate", &bitrate.to_string());
+        options.set("maxrate", &bitrate.to_string());
+        options.set("bufsize", &vbv_buffer_size.to_string());
+    }
+}
@@ -13,8 +13,24 @@ pub enum SlickscreenError {
     AudioCaptureError(String),
     #[error("Video encoder not found: {0}")]
     VideoEncoderNotFound(String),
+    #[error("Invalid encoder configuration: {0}")]
+    InvalidEncoderConfig(String),
     #[error("Unable to configure screen capture")]
     ScreenCaptureError(String),
+    #[error("Unable to capture input events: {0}")]
+    InputError(String),
+    #[error("Unable to capture the mouse cursor: {0}")]
+    CursorError(String),
+    #[error("Unable to configure overlay: {0}")]
+    OverlayError(String),
+    #[error("Unable to open camera: {0}")]
+    CameraError(String),
+    #[error("Invalid filter graph: {0}")]
+    FilterError(String),
+    #[error("Unable to open output: {0}")]
+    OutputError(String),
+    #[error("Unable to write recording manifest: {0}")]
+    ManifestError(String),
 
     #[error("unexpected error")]
     Unexpected,
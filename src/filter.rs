@@ -0,0 +1,365 @@
+use super::*;
+
+use ffmpeg_next::ffi::{
+    av_buffersink_get_h, av_buffersink_get_time_base, av_buffersink_get_w,
+    av_buffersrc_add_frame_flags, AVPixelFormat, AV_BUFFERSRC_FLAG_KEEP_REF,
+};
+use ffmpeg_next::filter;
+use ffmpeg_next::util::channel_layout::ChannelLayout;
+use ffmpeg_next::util::format::pixel::Pixel as ffmpeg_Pixel;
+use ffmpeg_next::util::format::sample::Sample as ffmpeg_Sample;
+use ffmpeg_next::util::frame::Audio as AudioFrame;
+use ffmpeg_next::util::frame::Video as VideoFrame;
+use ffmpeg_next::Rescale;
+
+/// A libavfilter graph given as a string, fed through a source named "in" and drained
+/// through a sink named "out". Timestamps are in microseconds on both ends
+struct FilterGraph {
+    graph: filter::Graph,
+    time_base: ffmpeg_next::Rational,
+}
+
+impl FilterGraph {
+    /// Build and validate the graph, `configure_sink` restricts what comes out of it
+    fn new(
+        spec: &str,
+        (source, source_args): (&str, &str),
+        sink: &str,
+        configure_sink: impl FnOnce(&mut filter::Context),
+    ) -> Result<Self, SlickscreenError> {
+        let error =
+            |e: ffmpeg_next::Error| SlickscreenError::FilterError(format!("{}: {}", spec, e));
+
+        let mut graph = filter::Graph::new();
+        let source_filter = filter::find(source).ok_or(ffmpeg_next::Error::FilterNotFound);
+        let sink_filter = filter::find(sink).ok_or(ffmpeg_next::Error::FilterNotFound);
+        graph
+            .add(&source_filter.map_err(error)?, "in", source_args)
+            .map_err(error)?;
+        configure_sink(
+            &mut graph
+                .add(&sink_filter.map_err(error)?, "out", "")
+                .map_err(error)?,
+        );
+        graph
+            .output("in", 0)
+            .and_then(|parser| parser.input("out", 0))
+            .and_then(|parser| parser.parse(spec))
+            .map_err(error)?;
+        graph.validate().map_err(error)?;
+
+        let time_base = {
+            let sink = graph.get("out").expect("filter graph has a sink");
+            unsafe { av_buffersink_get_time_base(sink.as_ptr()) }.into()
+        };
+        Ok(Self { graph, time_base })
+    }
+
+    /// The buffersink, to read the negotiated output parameters
+    fn sink(&mut self) -> filter::Context {
+        self.graph.get("out").expect("filter graph has a sink")
+    }
+
+    /// The frame keeps its buffers, the graph holds another reference until it is done
+    /// with them
+    fn push(&mut self, frame: &ffmpeg_next::Frame) -> Result<(), ffmpeg_next::Error> {
+        let mut source = self.graph.get("in").expect("filter graph has a source");
+        match unsafe {
+            av_buffersrc_add_frame_flags(
+                source.as_mut_ptr(),
+                frame.as_ptr() as *mut _,
+                AV_BUFFERSRC_FLAG_KEEP_REF as i32,
+            )
+        } {
+            0 => Ok(()),
+            e => Err(ffmpeg_next::Error::from(e)),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), ffmpeg_next::Error> {
+        let mut source = self.graph.get("in").expect("filter graph has a source");
+        source.source().flush()
+    }
+
+    /// Returns false if no filtered frame is ready
+    fn pull(&mut self, frame: &mut ffmpeg_next::Frame) -> bool {
+        let mut sink = self.graph.get("out").expect("filter graph has a sink");
+        if sink.sink().frame(frame).is_err() {
+            return false;
+        }
+        let pts = frame
+            .pts()
+            .map(|pts| pts.rescale(self.time_base, ffmpeg_next::Rational::new(1, 1000000)));
+        frame.set_pts(pts);
+        true
+    }
+}
+
+/// Runs captured frames through a libavfilter graph given as a string, e.g.
+/// `scale=1280:-2,eq=contrast=1.2`
+pub(crate) struct VideoFilter {
+    graph: FilterGraph,
+    width: u32,
+    height: u32,
+}
+
+impl VideoFilter {
+    /// Frames go in and come out as BGRA, the graph may change their size and timing. The
+    /// size that comes out must suit `encoder_format`, e.g. be even for yuv420p
+    pub fn new(
+        spec: &str,
+        width: u32,
+        height: u32,
+        encoder_format: ffmpeg_Pixel,
+    ) -> Result<Self, SlickscreenError> {
+        let source_args = format!(
+            "video_size={}x{}:pix_fmt={}:time_base=1/1000000:pixel_aspect=1/1",
+            width,
+            height,
+            AVPixelFormat::from(ffmpeg_Pixel::BGRA) as i32
+        );
+        let mut graph = FilterGraph::new(spec, ("buffer", &source_args), "buffersink", |sink| {
+            // Frames are converted for the encoder the same way with or without a filter
+            sink.set_pixel_format(ffmpeg_Pixel::BGRA)
+        })?;
+        let sink = graph.sink();
+        let (width, height) = unsafe {
+            (
+                av_buffersink_get_w(sink.as_ptr()) as u32,
+                av_buffersink_get_h(sink.as_ptr()) as u32,
+            )
+        };
+        // Subsampled chroma covers blocks of pixels, e.g. the encoder rejects odd sizes for
+        // yuv420p
+        let (block_width, block_height) = encoder_format.descriptor().map_or((1, 1), |d| {
+            (1u32 << d.log2_chroma_w(), 1u32 << d.log2_chroma_h())
+        });
+        if width % block_width != 0 || height % block_height != 0 {
+            return Err(SlickscreenError::FilterError(format!(
+                "{}: the output size {}x{} is not a multiple of {}x{} as {:?} requires",
+                spec, width, height, block_width, block_height, encoder_format
+            )));
+        }
+        Ok(Self {
+            graph,
+            width,
+            height,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Feed a frame with its pts in microseconds
+    pub fn push(&mut self, frame: &VideoFrame) -> Result<(), ffmpeg_next::Error> {
+        self.graph.push(frame)
+    }
+
+    /// Signal the end of the input, the remaining frames can then be pulled
+    pub fn flush(&mut self) -> Result<(), ffmpeg_next::Error> {
+        self.graph.flush()
+    }
+
+    /// The next filtered frame with its pts in microseconds, if one is ready
+    pub fn pull(&mut self) -> Option<VideoFrame> {
+        let mut frame = VideoFrame::empty();
+        if self.graph.pull(&mut frame) {
+            Some(frame)
+        } else {
+            None
+        }
+    }
+}
+
+/// Runs captured audio through a libavfilter graph given as a string, e.g.
+/// `highpass=f=80,afftdn`
+pub(crate) struct AudioFilter {
+    graph: FilterGraph,
+}
+
+impl AudioFilter {
+    /// Audio goes in and comes out in the encoder's format, rate and channel layout.
+    /// A non-zero `frame_size` makes every frame but the last one hold exactly that many
+    /// samples, filters such as loudnorm otherwise emit frames larger than the encoder takes
+    pub fn new(
+        spec: &str,
+        format: ffmpeg_Sample,
+        sample_rate: u32,
+        channel_layout: ChannelLayout,
+        frame_size: u32,
+    ) -> Result<Self, SlickscreenError> {
+        let source_args = format!(
+            "time_base=1/1000000:sample_rate={}:sample_fmt={}:channel_layout=0x{:x}",
+            sample_rate,
+            format.name(),
+            channel_layout.bits()
+        );
+        let mut graph = FilterGraph::new(spec, ("abuffer", &source_args), "abuffersink", |sink| {
+            // Filters such as loudnorm resample internally, this converts back
+            sink.set_sample_format(format);
+            sink.set_sample_rate(sample_rate);
+            sink.set_channel_layout(channel_layout);
+        })?;
+        if frame_size > 0 {
+            graph.sink().sink().set_frame_size(frame_size);
+        }
+        Ok(Self { graph })
+    }
+
+    /// Feed a frame with its pts in microseconds
+    pub fn push(&mut self, frame: &AudioFrame) -> Result<(), ffmpeg_next::Error> {
+        self.graph.push(frame)
+    }
+
+    /// Signal the end of the input, the remaining frames can then be pulled
+    pub fn flush(&mut self) -> Result<(), ffmpeg_next::Error> {
+        self.graph.flush()
+    }
+
+    /// The next filtered frame with its pts in microseconds, if one is ready
+    pub fn pull(&mut self) -> Option<AudioFrame> {
+        let mut frame = AudioFrame::empty();
+        if self.graph.pull(&mut frame) {
+            Some(frame)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+    const FRAME_SIZE: u32 = 1024;
+    const FORMAT: ffmpeg_Sample =
+        ffmpeg_Sample::I16(ffmpeg_next::util::format::sample::Type::Packed);
+
+    /// Frames of 10ms like the capture callback delivers, stereo 440Hz at -20dBFS
+    fn captured_audio(seconds: u32) -> Vec<AudioFrame> {
+        let samples_per_frame = SAMPLE_RATE as usize / 100;
+        (0..seconds as usize * 100)
+            .map(|index| {
+                let mut frame = AudioFrame::new(FORMAT, samples_per_frame, ChannelLayout::STEREO);
+                frame.set_rate(SAMPLE_RATE);
+                frame.set_pts(Some(index as i64 * 10_000));
+                let data = frame.data_mut(0);
+                for sample in 0..samples_per_frame {
+                    let t = (index * samples_per_frame + sample) as f64 / SAMPLE_RATE as f64;
+                    let value = (0.1
+                        * (2.0 * std::f64::consts::PI * 440.0 * t).sin()
+                        * i16::MAX as f64) as i16;
+                    for channel in 0..2 {
+                        let offset = (sample * 2 + channel) * 2;
+                        data[offset..offset + 2].copy_from_slice(&value.to_ne_bytes());
+                    }
+                }
+                frame
+            })
+            .collect()
+    }
+
+    /// Every pulled frame fits the encoder, only the last one may be short
+    fn assert_encoder_sized(spec: &str) {
+        let mut filter =
+            AudioFilter::new(spec, FORMAT, SAMPLE_RATE, ChannelLayout::STEREO, FRAME_SIZE).unwrap();
+        let mut sizes = Vec::new();
+        for frame in captured_audio(4) {
+            filter.push(&frame).unwrap();
+            while let Some(filtered) = filter.pull() {
+                sizes.push(filtered.samples());
+            }
+        }
+        filter.flush().unwrap();
+        while let Some(filtered) = filter.pull() {
+            sizes.push(filtered.samples());
+        }
+
+        assert!(sizes.len() > 1, "{}: {:?}", spec, sizes);
+        let (last, full) = sizes.split_last().unwrap();
+        assert!(
+            full.iter().all(|&size| size == FRAME_SIZE as usize),
+            "{}: {:?}",
+            spec,
+            sizes
+        );
+        assert!(
+            *last > 0 && *last <= FRAME_SIZE as usize,
+            "{}: {}",
+            spec,
+            last
+        );
+    }
+
+    #[test]
+    fn loudnorm_frames_fit_the_encoder() {
+        assert_encoder_sized("loudnorm");
+    }
+
+    #[test]
+    fn afftdn_frames_fit_the_encoder() {
+        assert_encoder_sized("afftdn");
+    }
+
+    fn video_filter(spec: &str) -> Result<VideoFilter, SlickscreenError> {
+        VideoFilter::new(spec, 64, 48, ffmpeg_Pixel::YUV420P)
+    }
+
+    #[test]
+    fn invalid_video_graphs_are_filter_errors() {
+        for spec in ["no_such_filter", "scale=w=abc", "crop=33:24", "crop=32:25"] {
+            assert!(
+                matches!(video_filter(spec), Err(SlickscreenError::FilterError(_))),
+                "{}",
+                spec
+            );
+        }
+        // Odd sizes are fine without chroma subsampling
+        assert!(VideoFilter::new("crop=33:25", 64, 48, ffmpeg_Pixel::YUV444P).is_ok());
+    }
+
+    #[test]
+    fn video_filter_reports_the_scaled_size() {
+        let mut filter = video_filter("scale=32:-2").unwrap();
+        assert_eq!((filter.width(), filter.height()), (32, 24));
+
+        let mut frame = VideoFrame::new(ffmpeg_Pixel::BGRA, 64, 48);
+        frame.set_pts(Some(0));
+        filter.push(&frame).unwrap();
+        filter.flush().unwrap();
+        let filtered = filter.pull().unwrap();
+        assert_eq!((filtered.width(), filtered.height()), (32, 24));
+        assert_eq!(filtered.format(), ffmpeg_Pixel::BGRA);
+    }
+
+    #[test]
+    fn video_filter_pts_are_in_microseconds() {
+        // fps uses a time base of 1/30 inside the graph
+        let mut filter = video_filter("fps=30").unwrap();
+        for index in 0..10 {
+            let mut frame = VideoFrame::new(ffmpeg_Pixel::BGRA, 64, 48);
+            frame.set_pts(Some(index * 1_000_000 / 30));
+            filter.push(&frame).unwrap();
+        }
+        filter.flush().unwrap();
+        let mut pts = Vec::new();
+        while let Some(filtered) = filter.pull() {
+            pts.push(filtered.pts().unwrap());
+        }
+        assert_eq!(pts.len(), 10);
+        for (index, pts) in pts.into_iter().enumerate() {
+            assert!(
+                (pts - index as i64 * 1_000_000 / 30).abs() <= 1,
+                "frame {} at {}",
+                index,
+                pts
+            );
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use super::*;
+
+use crossbeam::channel::Receiver;
+
+/// A mouse click or key press made while recording
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InputEvent {
+    /// `x` and `y` are in pixels from the top left corner of the captured display
+    ButtonPress { x: i64, y: i64, button: u32 },
+    /// `key` is a readable key name such as `a`, `Enter` or `Ctrl`
+    KeyPress { key: String },
+}
+
+/// Draw mouse clicks as expanding rings and recent key presses as a caption, e.g. for
+/// tutorials
+#[derive(Clone, Debug)]
+pub struct InputOverlayConfig {
+    pub clicks: bool,
+    pub keystrokes: bool,
+    /// TrueType or OpenType font for the keystroke caption, required for keystrokes
+    pub font_path: Option<std::path::PathBuf>,
+    /// Height of the caption text in pixels
+    pub font_size: f32,
+}
+
+impl Default for InputOverlayConfig {
+    fn default() -> Self {
+        InputOverlayConfig {
+            clicks: true,
+            keystrokes: false,
+            font_path: None,
+            font_size: 32.0,
+        }
+    }
+}
+
+/// Where input events for the overlays and the input log come from
+#[derive(Clone, Debug)]
+pub enum InputSource {
+    /// Global mouse and keyboard events, XInput2 raw events on X11
+    System,
+    /// Events sent by the application, e.g. to replay or synthesize input
+    Channel(Receiver<InputEvent>),
+}
+
+impl Default for InputSource {
+    fn default() -> Self {
+        InputSource::System
+    }
+}
+
+impl InputSource {
+    pub(crate) fn open(&self) -> Result<Box<dyn InputEventSource>, SlickscreenError> {
+        match self {
+            InputSource::System => Ok(Box::new(xinput::XInputSource::open()?)),
+            InputSource::Channel(receiver) => Ok(Box::new(ChannelInputSource {
+                receiver: receiver.clone(),
+            })),
+        }
+    }
+}
+
+/// Produces the input events that happened since it was last polled
+pub(crate) trait InputEventSource: Send {
+    /// Append new events to `events`, must not block
+    fn poll(&mut self, events: &mut Vec<InputEvent>);
+}
+
+struct ChannelInputSource {
+    receiver: Receiver<InputEvent>,
+}
+
+impl InputEventSource for ChannelInputSource {
+    fn poll(&mut self, events: &mut Vec<InputEvent>) {
+        events.extend(self.receiver.try_iter());
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod xinput {
+    use super::*;
+
+    use std::ffi::CStr;
+    use x11::{xinput2, xlib};
+
+    /// Global raw mouse button and key press events from the X server
+    pub(super) struct XInputSource {
+        display: *mut xlib::Display,
+        root: xlib::Window,
+        opcode: i32,
+    }
+
+    // The display connection is only ever used by the thread that owns the source
+    unsafe impl Send for XInputSource {}
+
+    /// Shorter names for the keys that show up most in captions
+    fn readable_key_name(keysym_name: &str) -> String {
+        match keysym_name {
+            "Return" | "KP_Enter" => "Enter",
+            "Escape" => "Esc",
+            "BackSpace" => "Backspace",
+            "space" => "Space",
+            "Control_L" | "Control_R" => "Ctrl",
+            "Shift_L" | "Shift_R" => "Shift",
+            "Alt_L" | "Alt_R" | "ISO_Level3_Shift" => "Alt",
+            "Super_L" | "Super_R" => "Super",
+            "Prior" => "PageUp",
+            "Next" => "PageDown",
+            name => name,
+        }
+        .to_string()
+    }
+
+    impl XInputSource {
+        pub fn open() -> Result<Self, SlickscreenError> {
+            unsafe {
+                let display = xlib::XOpenDisplay(std::ptr::null());
+                if display.is_null() {
+                    return Err(SlickscreenError::InputError(
+                        "unable to connect to the X server".to_string(),
+                    ));
+                }
+                let source = Self {
+                    display,
+                    root: xlib::XDefaultRootWindow(display),
+                    opcode: 0,
+                };
+                source.select_raw_events()
+            }
+        }
+
+        unsafe fn select_raw_events(mut self) -> Result<Self, SlickscreenError> {
+            let (mut event_base, mut error_base) = (0, 0);
+            let extension = CStr::from_bytes_with_nul(b"XInputExtension\0").unwrap();
+            if xlib::XQueryExtension(
+                self.display,
+                extension.as_ptr(),
+                &mut self.opcode,
+                &mut event_base,
+                &mut error_base,
+            ) == 0
+            {
+                return Err(SlickscreenError::InputError(
+                    "the X server does not support XInput".to_string(),
+                ));
+            }
+            let (mut major, mut minor) = (2, 0);
+            if xinput2::XIQueryVersion(self.display, &mut major, &mut minor) != xlib::Success as i32
+            {
+                return Err(SlickscreenError::InputError(
+                    "the X server does not support XInput 2".to_string(),
+                ));
+            }
+
+            let mut mask = [0u8; (xinput2::XI_LASTEVENT as usize >> 3) + 1];
+            for event in [xinput2::XI_RawButtonPress, xinput2::XI_RawKeyPress] {
+                mask[event as usize >> 3] |= 1 << (event as usize & 7);
+            }
+            let mut event_mask = xinput2::XIEventMask {
+                deviceid: xinput2::XIAllMasterDevices,
+                mask_len: mask.len() as i32,
+                mask: mask.as_mut_ptr(),
+            };
+            xinput2::XISelectEvents(self.display, self.root, &mut event_mask, 1);
+            xlib::XFlush(self.display);
+            Ok(self)
+        }
+
+        unsafe fn pointer_position(&self) -> (i64, i64) {
+            let (mut root, mut child) = (0, 0);
+            let (mut root_x, mut root_y, mut window_x, mut window_y) = (0, 0, 0, 0);
+            let mut buttons = 0;
+            xlib::XQueryPointer(
+                self.display,
+                self.root,
+                &mut root,
+                &mut child,
+                &mut root_x,
+                &mut root_y,
+                &mut window_x,
+                &mut window_y,
+                &mut buttons,
+            );
+            (root_x as i64, root_y as i64)
+        }
+
+        unsafe fn key_name(&self, keycode: i32) -> Option<String> {
+            let keysym = xlib::XkbKeycodeToKeysym(self.display, keycode as xlib::KeyCode, 0, 0);
+            let name = xlib::XKeysymToString(keysym);
+            if name.is_null() {
+                None
+            } else {
+                Some(readable_key_name(&CStr::from_ptr(name).to_string_lossy()))
+            }
+        }
+    }
+
+    impl InputEventSource for XInputSource {
+        fn poll(&mut self, events: &mut Vec<InputEvent>) {
+            unsafe {
+                while xlib::XPending(self.display) > 0 {
+                    let mut event = std::mem::zeroed::<xlib::XEvent>();
+                    xlib::XNextEvent(self.display, &mut event);
+                    if event.get_type() != xlib::GenericEvent {
+                        continue;
+                    }
+                    let mut cookie = xlib::XGenericEventCookie::from(event);
+                    if cookie.extension != self.opcode
+                        || xlib::XGetEventData(self.display, &mut cookie) == 0
+                    {
+                        continue;
+                    }
+                    let raw_event = &*(cookie.data as *const xinput2::XIRawEvent);
+                    match cookie.evtype {
+                        xinput2::XI_RawButtonPress => {
+                            let (x, y) = self.pointer_position();
+                            events.push(InputEvent::ButtonPress {
+                                x,
+                                y,
+                                button: raw_event.detail as u32,
+                            });
+                        }
+                        xinput2::XI_RawKeyPress => {
+                            if let Some(key) = self.key_name(raw_event.detail) {
+                                events.push(InputEvent::KeyPress { key });
+                            }
+                        }
+                        _ => {}
+                    }
+                    xlib::XFreeEventData(self.display, &mut cookie);
+                }
+            }
+        }
+    }
+
+    impl Drop for XInputSource {
+        fn drop(&mut self) {
+            unsafe {
+                xlib::XCloseDisplay(self.display);
+            }
+        }
+    }
+}
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+mod xinput {
+    use super::*;
+
+    pub(super) struct XInputSource;
+
+    impl XInputSource {
+        pub fn open() -> Result<Self, SlickscreenError> {
+            Err(SlickscreenError::InputError(
+                "global input events are only supported on X11".to_string(),
+            ))
+        }
+    }
+
+    impl InputEventSource for XInputSource {
+        fn poll(&mut self, _events: &mut Vec<InputEvent>) {}
+    }
+}
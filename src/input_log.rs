@@ -0,0 +1,374 @@
+use super::*;
+
+use ffmpeg_next::codec::encoder;
+use ffmpeg_next::codec::encoder::encoder::Encoder;
+use ffmpeg_next::codec::Context;
+
+/// How long a cue stays up unless the next input event replaces it
+const CUE_DURATION_US: i64 = 2_000_000;
+/// Key presses closer together than this are merged into one cue
+const KEY_MERGE_INTERVAL_US: i64 = 1_000_000;
+/// Most key presses in one cue, further keys start a new cue
+const CUE_MAX_KEYS: usize = 12;
+/// Largest encoded cue, the text of a single cue is short
+const MAX_CUE_SIZE: usize = 4096;
+
+/// Default style for the ASS dialogue lines the text subtitle encoders take as input
+const ASS_HEADER: &str = "[Script Info]\r\n\
+ScriptType: v4.00+\r\n\
+PlayResX: 384\r\n\
+PlayResY: 288\r\n\
+\r\n\
+[V4+ Styles]\r\n\
+Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, \
+Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, \
+Shadow, Alignment, MarginL, MarginR, MarginV, Encoding\r\n\
+Style: Default,Arial,16,&Hffffff,&Hffffff,&H0,&H0,0,0,0,0,100,100,0,0,1,1,0,2,10,10,10,0\r\n\
+\r\n\
+[Events]\r\n\
+Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\r\n";
+
+/// Text subtitle codec for the input event log, each output only keeps the log if its
+/// container supports the codec
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SubtitleFormat {
+    /// For MKV and WebM
+    WebVtt,
+    /// For MKV
+    Srt,
+    /// For MP4 and MOV
+    MovText,
+}
+
+impl std::str::FromStr for SubtitleFormat {
+    type Err = SlickscreenError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "webvtt" | "vtt" => Ok(SubtitleFormat::WebVtt),
+            "srt" | "subrip" => Ok(SubtitleFormat::Srt),
+            "mov_text" | "tx3g" => Ok(SubtitleFormat::MovText),
+            _ => Err(SlickscreenError::InvalidEncoderConfig(format!(
+                "unknown subtitle format {}, expected webvtt, srt or mov_text",
+                s
+            ))),
+        }
+    }
+}
+
+impl SubtitleFormat {
+    /// The format that suits a file name, based on its extension
+    pub fn for_file(path: &str) -> Option<Self> {
+        let extension = std::path::Path::new(path)
+            .extension()?
+            .to_string_lossy()
+            .to_lowercase();
+        match extension.as_str() {
+            "mp4" | "m4v" | "mov" => Some(SubtitleFormat::MovText),
+            "webm" => Some(SubtitleFormat::WebVtt),
+            "mkv" => Some(SubtitleFormat::Srt),
+            _ => None,
+        }
+    }
+
+    fn encoder_name(&self) -> &'static str {
+        match self {
+            SubtitleFormat::WebVtt => "webvtt",
+            SubtitleFormat::Srt => "srt",
+            SubtitleFormat::MovText => "mov_text",
+        }
+    }
+}
+
+/// The cue that is shown until the next input event or its duration runs out
+struct Cue {
+    start: i64,
+    last_event: i64,
+    text: String,
+    /// Number of merged key presses, 0 for clicks
+    keys: usize,
+}
+
+/// A cue ready to be written, times in microseconds
+#[derive(Debug, PartialEq)]
+struct FinishedCue {
+    start: i64,
+    end: i64,
+    text: String,
+}
+
+/// Groups input events into cues, typing becomes one cue per burst of keys
+#[derive(Default)]
+struct CueBuilder {
+    pending: Option<Cue>,
+}
+
+impl CueBuilder {
+    /// Returns the previous cue if this event ends it
+    fn input_event(&mut self, text: String, is_key: bool, pts: i64) -> Option<FinishedCue> {
+        if let Some(cue) = self.pending.as_mut() {
+            if is_key
+                && cue.keys > 0
+                && cue.keys < CUE_MAX_KEYS
+                && pts - cue.last_event < KEY_MERGE_INTERVAL_US
+                && pts - cue.start < CUE_DURATION_US
+            {
+                cue.text.push(' ');
+                cue.text.push_str(&text);
+                cue.last_event = pts;
+                cue.keys += 1;
+                return None;
+            }
+        }
+        let finished = self.flush(pts);
+        self.pending = Some(Cue {
+            start: pts,
+            last_event: pts,
+            text,
+            keys: is_key as usize,
+        });
+        finished
+    }
+
+    /// Returns the pending cue if it was up for its full duration by `pts`
+    fn expire(&mut self, pts: i64) -> Option<FinishedCue> {
+        match &self.pending {
+            Some(cue) if pts >= cue.last_event + CUE_DURATION_US => self.flush(pts),
+            _ => None,
+        }
+    }
+
+    /// Returns the pending cue, it ends at `pts` at the latest
+    fn flush(&mut self, pts: i64) -> Option<FinishedCue> {
+        let cue = self.pending.take()?;
+        let end = pts
+            .min(cue.last_event + CUE_DURATION_US)
+            .max(cue.start + 1000);
+        Some(FinishedCue {
+            start: cue.start,
+            end,
+            text: cue.text,
+        })
+    }
+}
+
+/// Writes input events as a text subtitle stream, so viewers can toggle them and tools
+/// can parse them
+pub(crate) struct InputLog {
+    encoder: encoder::subtitle::Encoder,
+    sender: SlickscreenMessageSender,
+    stats: std::sync::Arc<StatsCounters>,
+    cues: CueBuilder,
+    buffer: Vec<u8>,
+}
+
+impl InputLog {
+    pub fn new(
+        format: SubtitleFormat,
+        sender: SlickscreenMessageSender,
+        stats: std::sync::Arc<StatsCounters>,
+    ) -> Result<(Self, StreamDescription), SlickscreenError> {
+        let codec = encoder::find_by_name(format.encoder_name()).ok_or_else(|| {
+            SlickscreenError::InvalidEncoderConfig(format!(
+                "{} encoder not found",
+                format.encoder_name()
+            ))
+        })?;
+
+        let mut encoder = encoder::subtitle::Subtitle(Encoder(Context::new()));
+        encoder.set_time_base(ffmpeg_next::util::rational::Rational::new(1, 1000000));
+        unsafe {
+            // Freed together with the codec context
+            let context = encoder.as_mut_ptr();
+            let header = ffmpeg_next::ffi::av_mallocz(ASS_HEADER.len() + 1) as *mut u8;
+            std::ptr::copy_nonoverlapping(ASS_HEADER.as_ptr(), header, ASS_HEADER.len());
+            (*context).subtitle_header = header;
+            (*context).subtitle_header_size = ASS_HEADER.len() as i32;
+        }
+        let encoder = encoder
+            .open_as(codec)
+            .map_err(|e| SlickscreenError::InvalidEncoderConfig(e.to_string()))?;
+
+        let stream_description = StreamDescription {
+            kind: StreamKind::Subtitle,
+            codec,
+            parameters: ffmpeg_next::codec::Parameters::from(&encoder),
+            time_base: ffmpeg_next::util::rational::Rational::new(1, 1000000),
+            info: StreamInfo {
+                kind: StreamKind::Subtitle,
+                codec: codec.name().to_string(),
+                width: None,
+                height: None,
+                sample_rate: None,
+                channels: None,
+            },
+        };
+
+        Ok((
+            Self {
+                encoder,
+                sender,
+                stats,
+                cues: CueBuilder::default(),
+                buffer: vec![0; MAX_CUE_SIZE],
+            },
+            stream_description,
+        ))
+    }
+
+    pub fn input_event(&mut self, event: &InputEvent, pts: i64) {
+        let (text, is_key) = match event {
+            InputEvent::ButtonPress { x, y, button } => {
+                let button = match button {
+                    1 => "Left click",
+                    2 => "Middle click",
+                    3 => "Right click",
+                    // Scroll wheels
+                    _ => return,
+                };
+                (format!("{} at {}, {}", button, x, y), false)
+            }
+            InputEvent::KeyPress { key } => (key.clone(), true),
+        };
+
+        let finished = self.cues.input_event(text, is_key, pts);
+        self.write(finished);
+    }
+
+    /// Write the pending cue once its duration has run out, so it does not wait for the
+    /// next input event
+    pub fn expire(&mut self, pts: i64) {
+        let finished = self.cues.expire(pts);
+        self.write(finished);
+    }
+
+    /// Write the pending cue, it ends at `pts` at the latest
+    pub fn flush(&mut self, pts: i64) {
+        let finished = self.cues.flush(pts);
+        self.write(finished);
+    }
+
+    fn write(&mut self, cue: Option<FinishedCue>) {
+        if let Some(cue) = cue {
+            if let Err(e) = self.write_cue(cue.start, cue.end, &cue.text) {
+                println!("Error while encoding input log: {}", e);
+            }
+        }
+    }
+
+    fn write_cue(&mut self, start: i64, end: i64, text: &str) -> Result<(), ffmpeg_next::Error> {
+        let mut subtitle = ffmpeg_next::Subtitle::new();
+        // Subtitle timestamps are in AV_TIME_BASE, the same microseconds as the recording
+        subtitle.set_pts(Some(start));
+        subtitle.set_start(0);
+        subtitle.set_end(((end - start) / 1000) as u32);
+        if let ffmpeg_next::subtitle::RectMut::Ass(mut ass) =
+            subtitle.add_rect(ffmpeg_next::subtitle::Type::Ass)
+        {
+            // ReadOrder, Layer, Style, Name, MarginL, MarginR, MarginV, Effect, Text
+            ass.set(&format!(
+                "0,0,Default,,0,0,0,,{}",
+                text.replace('{', "\\{").replace('\n', "\\N")
+            ));
+        }
+
+        let size = unsafe {
+            let size = ffmpeg_next::ffi::avcodec_encode_subtitle(
+                self.encoder.as_mut_ptr(),
+                self.buffer.as_mut_ptr(),
+                self.buffer.len() as i32,
+                subtitle.as_ptr(),
+            );
+            ffmpeg_next::ffi::avsubtitle_free(subtitle.as_mut_ptr());
+            size
+        };
+        if size < 0 {
+            return Err(ffmpeg_next::Error::from(size));
+        }
+
+        let mut packet = ffmpeg_next::Packet::copy(&self.buffer[..size as usize]);
+        packet.set_pts(Some(start));
+        packet.set_dts(Some(start));
+        packet.set_duration(end - start);
+        match self.sender.send(SlickscreenMessage::Subtitle(packet)) {
+            Ok(dropped) => StatsCounters::add(&self.stats.packets_dropped, dropped),
+            Err(e) => println!("Unable to send input log packet - {}", e),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(cues: &mut CueBuilder, key: &str, pts: i64) -> Option<FinishedCue> {
+        cues.input_event(key.to_string(), true, pts)
+    }
+
+    fn cue(start: i64, end: i64, text: &str) -> Option<FinishedCue> {
+        Some(FinishedCue {
+            start,
+            end,
+            text: text.to_string(),
+        })
+    }
+
+    #[test]
+    fn key_presses_close_together_share_a_cue() {
+        let mut cues = CueBuilder::default();
+        assert_eq!(key(&mut cues, "Ctrl+C", 0), None);
+        assert_eq!(key(&mut cues, "Ctrl+V", 500_000), None);
+        // The gap ends the cue when the next key arrives
+        assert_eq!(
+            key(&mut cues, "Enter", 1_600_000),
+            cue(0, 1_600_000, "Ctrl+C Ctrl+V")
+        );
+        // Clicks are never merged
+        assert_eq!(
+            cues.input_event("Left click at 1, 2".to_string(), false, 1_700_000),
+            cue(1_600_000, 1_700_000, "Enter")
+        );
+        assert_eq!(
+            key(&mut cues, "a", 1_800_000),
+            cue(1_700_000, 1_800_000, "Left click at 1, 2")
+        );
+    }
+
+    #[test]
+    fn long_typing_is_split_into_cues() {
+        let mut cues = CueBuilder::default();
+        let mut finished = Vec::new();
+        // Twenty keys, 100ms apart
+        for index in 0..20 {
+            finished.extend(key(&mut cues, "a", index * 100_000));
+        }
+        finished.extend(cues.flush(2_000_000));
+        assert_eq!(finished.len(), 2);
+        assert_eq!(finished[0].text.split(' ').count(), CUE_MAX_KEYS);
+        assert_eq!(finished[0].end, finished[1].start);
+        assert_eq!(finished[1].text.split(' ').count(), 20 - CUE_MAX_KEYS);
+
+        // Keys arriving steadily below the merge interval still end a cue after its duration
+        let mut cues = CueBuilder::default();
+        let mut finished = Vec::new();
+        for index in 0..5 {
+            finished.extend(key(&mut cues, "a", index * 900_000));
+        }
+        assert_eq!(finished, vec![cue(0, 2_700_000, "a a a").unwrap()]);
+    }
+
+    #[test]
+    fn cues_expire_without_further_input() {
+        let mut cues = CueBuilder::default();
+        key(&mut cues, "a", 1_000_000);
+        key(&mut cues, "b", 1_500_000);
+        assert_eq!(cues.expire(3_400_000), None);
+        assert_eq!(cues.expire(3_600_000), cue(1_000_000, 3_500_000, "a b"));
+        assert_eq!(cues.expire(10_000_000), None);
+
+        // Flushing right after an event still gives the cue a length
+        key(&mut cues, "c", 4_000_000);
+        assert_eq!(cues.flush(4_000_000), cue(4_000_000, 4_001_000, "c"));
+    }
+}
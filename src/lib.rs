@@ -1,13 +1,42 @@
 mod audio_recorder;
+mod camera;
+mod control;
+mod cursor;
+mod encoding;
 mod error;
+mod filter;
+mod input;
+mod input_log;
+mod manifest;
+mod output;
+mod overlay;
+mod queue;
+mod sink;
+mod stats;
 mod util;
 mod video_recorder;
 mod worker;
 
+pub use audio_recorder::NoiseGateConfig;
+pub use camera::{CameraConfig, CameraDevice};
+pub use control::*;
+pub use cursor::CursorMode;
+pub use encoding::*;
 pub use error::*;
+pub use input::{InputEvent, InputOverlayConfig, InputSource};
+pub use input_log::SubtitleFormat;
+pub use output::*;
+pub use overlay::{MaskRegion, MaskStyle, OverlayPosition, TextOverlayConfig};
+pub use queue::DropPolicy;
+use queue::*;
+pub use stats::SlickscreenStats;
 use util::*;
 
 use audio_recorder::*;
+use manifest::*;
+use overlay::{insert_mask, MaskList};
+use sink::*;
+use stats::StatsCounters;
 use video_recorder::*;
 
 use cpal::traits::StreamTrait;
@@ -17,6 +46,7 @@ enum SlickscreenMessage {
     Quit,
     Audio(ffmpeg_next::codec::packet::Packet),
     Video(ffmpeg_next::codec::packet::Packet),
+    Subtitle(ffmpeg_next::codec::packet::Packet),
 }
 
 impl From<worker::WorkerControlMessage> for SlickscreenMessage {
@@ -27,163 +57,345 @@ impl From<worker::WorkerControlMessage> for SlickscreenMessage {
     }
 }
 
-type SlickscreenMessageSender = crossbeam::channel::Sender<SlickscreenMessage>;
+type SlickscreenMessageSender = PacketSender;
 type SlickscreenMessageReceiver = crossbeam::channel::Receiver<SlickscreenMessage>;
 
 #[derive(Clone, Debug)]
 pub struct SlickscreenConfig {
-    pub output_file: Option<String>,
+    /// Every output receives the same encoded streams
+    pub outputs: Vec<SlickscreenOutput>,
+    /// Produce JPEG preview images of the capture, see `SlickscreenControl::preview_jpeg`
+    pub preview: Option<PreviewConfig>,
+    /// Open all outputs but wait for `resume` before capturing anything
+    pub start_paused: bool,
+    /// Written to the container metadata and the manifest
+    pub title: Option<String>,
+    pub author: Option<String>,
+    /// Write a JSON document describing the recording to this path when it stops
+    pub manifest_file: Option<String>,
+    pub profile: EncoderProfile,
+    /// Overrides the rate control of the profile
+    pub rate_control: Option<RateControl>,
+    /// Raw video encoder options applied last, e.g. `("preset", "veryfast")`
+    pub encoder_options: Vec<(String, String)>,
+    /// Maximum time between two video keyframes, shorter intervals make seeking faster
+    /// at the cost of larger files
+    pub keyframe_interval: std::time::Duration,
+    /// Range of the encoded video, colors are always converted with BT.709 coefficients
+    pub color_range: ColorRange,
+    /// Picks the video encoder and its pixel format
+    pub chroma_format: ChromaFormat,
+    /// Number of video encoder threads, 0 lets the encoder decide
+    pub encoder_threads: usize,
+    pub encoder_threading: EncoderThreading,
+    /// Captured frames that may wait for the encoder before `frame_drop_policy` applies
+    pub frame_queue_capacity: usize,
+    pub frame_drop_policy: DropPolicy,
+    /// Encoded packets that may wait for the outputs before `packet_drop_policy` applies
+    pub packet_queue_capacity: usize,
+    /// Only the encoder threads wait when this is `Block`, capture keeps running
+    pub packet_drop_policy: DropPolicy,
+    /// The screen capture itself never includes the mouse cursor, it is drawn separately
+    pub cursor: CursorMode,
+    /// Visualize mouse clicks and key presses
+    pub input_overlay: Option<InputOverlayConfig>,
+    /// Show a camera as a picture-in-picture on top of the screen
+    pub camera: Option<CameraConfig>,
+    /// Burn a timestamp or other text into every frame
+    pub text_overlay: Option<TextOverlayConfig>,
+    /// libavfilter graph applied to the captured frames before they are encoded, e.g.
+    /// `scale=1280:-2`. Masks and overlays are drawn before it
+    pub video_filter: Option<String>,
+    /// libavfilter graph applied to the captured audio before it is encoded, e.g.
+    /// `highpass=f=80,afftdn`
+    pub audio_filter: Option<String>,
+    /// Silence the audio while it is quieter than a threshold
+    pub noise_gate: Option<NoiseGateConfig>,
+    /// Normalize the audio to this integrated loudness in LUFS while recording. EBU R128
+    /// recommends -23, online video is usually around -14 to -16
+    pub loudness_target: Option<f64>,
+    /// Regions hidden from the recording, see `SlickscreenControl::set_mask` for changing
+    /// them while recording
+    pub masks: Vec<MaskRegion>,
+    /// Record clicks and key presses as a subtitle stream in the outputs that support it
+    pub input_log: Option<SubtitleFormat>,
+    /// Used by the input overlay and the input log
+    pub input_source: InputSource,
 }
 
 impl Default for SlickscreenConfig {
     fn default() -> Self {
-        SlickscreenConfig { output_file: None }
+        SlickscreenConfig {
+            outputs: Vec::new(),
+            preview: None,
+            start_paused: false,
+            title: None,
+            author: None,
+            manifest_file: None,
+            profile: EncoderProfile::default(),
+            rate_control: None,
+            encoder_options: Vec::new(),
+            keyframe_interval: std::time::Duration::from_secs(2),
+            color_range: ColorRange::default(),
+            chroma_format: ChromaFormat::default(),
+            encoder_threads: 0,
+            encoder_threading: EncoderThreading::default(),
+            frame_queue_capacity: 4,
+            frame_drop_policy: DropPolicy::DropOldest,
+            packet_queue_capacity: 1000,
+            packet_drop_policy: DropPolicy::Block,
+            cursor: CursorMode::default(),
+            input_overlay: None,
+            camera: None,
+            text_overlay: None,
+            video_filter: None,
+            audio_filter: None,
+            noise_gate: None,
+            loudness_target: None,
+            masks: Vec::new(),
+            input_log: None,
+            input_source: InputSource::default(),
+        }
     }
 }
 
+/// Stop the recorders when a later step of `Slickscreen::new` fails. Their packets are
+/// discarded meanwhile, so none of them waits on a full packet queue
+fn discard_recorders(
+    control_sender: &crossbeam::channel::Sender<SlickscreenMessage>,
+    control_receiver: &SlickscreenMessageReceiver,
+    audio_recorder: AudioRecorder,
+    video_recorder: Option<VideoRecorder>,
+) {
+    let control_receiver = control_receiver.clone();
+    let discard = std::thread::spawn(move || {
+        for msg in control_receiver.iter() {
+            if let SlickscreenMessage::Quit = msg {
+                return;
+            }
+        }
+    });
+    let _ = audio_recorder.worker.stop();
+    if let Some(video_recorder) = video_recorder {
+        let _ = video_recorder.stop();
+    }
+    let _ = control_sender.send(SlickscreenMessage::Quit);
+    let _ = discard.join();
+}
+
 pub struct Slickscreen {
     worker: worker::Worker<SlickscreenMessage>,
     audio_recorder: AudioRecorder,
     video_recorder: VideoRecorder,
+    control: SlickscreenControl,
+    recording_info: RecordingInfo,
+    manifest_file: Option<String>,
 }
 
 impl Slickscreen {
     pub fn new(config: SlickscreenConfig) -> Result<Self, SlickscreenError> {
         ffmpeg_next::init().map_err(|_e| SlickscreenError::FFmpegInitError)?;
 
-        let time_reference = SlickscreenTime::new(std::time::Instant::now());
+        if config.outputs.is_empty() {
+            return Err(SlickscreenError::OutputError(
+                "no output selected".to_string(),
+            ));
+        }
 
-        let output_file_name = config
-            .output_file
-            .as_ref()
-            .expect("no output file selected")
-            .to_string();
-        let worker =
-            worker::Worker::new_consumer(move |control_receiver: SlickscreenMessageReceiver| {
-                let mut ffmpeg_output =
-                    ffmpeg_next::format::output(&output_file_name).expect("no output file");
-                let stream_time_base = ffmpeg_next::util::rational::Rational::new(1, 1000000);
-
-                let mut aac_encoder = ffmpeg_next::codec::encoder::audio::Audio(
-                    ffmpeg_next::codec::encoder::encoder::Encoder(
-                        ffmpeg_next::codec::Context::new(),
-                    ),
-                );
-                aac_encoder.set_time_base(stream_time_base);
-                aac_encoder.set_rate(48000);
-                aac_encoder.set_format(ffmpeg_next::util::format::Sample::I16(
-                    ffmpeg_next::util::format::sample::Type::Packed,
-                ));
-                aac_encoder.set_channels(2);
-                aac_encoder
-                    .set_channel_layout(ffmpeg_next::util::channel_layout::ChannelLayout::STEREO);
-                let aac_codec = aac_encoder
-                    .open_as(
-                        ffmpeg_next::codec::encoder::find_by_name("libfdk_aac")
-                            .expect("aac not found"),
-                    )
-                    .expect("unable to open aac codec");
-
-                let mut aac_stream = ffmpeg_output
-                    .add_stream(aac_codec.codec())
-                    .expect("must be able to add aac stream");
-                aac_stream.set_time_base(stream_time_base);
-                aac_stream.set_parameters(ffmpeg_next::codec::Parameters::from(aac_codec));
-                let aac_stream_index = aac_stream.index();
-
-                let mut h264_encoder = ffmpeg_next::codec::encoder::video::Video(
-                    ffmpeg_next::codec::encoder::encoder::Encoder(
-                        ffmpeg_next::codec::Context::new(),
-                    ),
-                );
-                h264_encoder.set_time_base(stream_time_base);
-                h264_encoder.set_format(ffmpeg_next::util::format::pixel::Pixel::YUV420P);
-                h264_encoder.set_width(1920);
-                h264_encoder.set_height(1080);
-                h264_encoder.set_gop(4096);
-                h264_encoder.set_max_b_frames(0);
-                h264_encoder.set_colorspace(ffmpeg_next::util::color::Space::BT709);
-                h264_encoder.set_color_range(ffmpeg_next::util::color::Range::JPEG);
-                h264_encoder.set_me_range(16);
-                h264_encoder.set_qmin(10);
-                h264_encoder.set_qmax(51);
-                let mut h264_encoder_options = ffmpeg_next::Dictionary::new();
-                h264_encoder_options.set("preset", "medium");
-                h264_encoder_options.set("tune", "zerolatency");
-                h264_encoder_options.set("level", "4.2");
-                h264_encoder_options.set("profile", "high");
-                h264_encoder_options.set("refs", "1");
-                h264_encoder_options.set("crf", "15");
-                h264_encoder_options.set("qdiff", "4");
-                h264_encoder_options.set("qcompress", "0.6");
-                h264_encoder_options.set("color_primaries", "bt709");
-                h264_encoder_options.set("color_trc", "bt709");
-                let h264_codec = h264_encoder
-                    .open_as_with(
-                        ffmpeg_next::codec::encoder::find_by_name("libx264")
-                            .expect("h264 not found"),
-                        h264_encoder_options,
-                    )
-                    .expect("unable to open h264 codec");
-                let mut h264_stream = ffmpeg_output
-                    .add_stream(h264_codec.codec())
-                    .expect("could not add h264 stream");
-                h264_stream.set_time_base(stream_time_base);
-                h264_stream.set_parameters(ffmpeg_next::codec::Parameters::from(h264_codec));
-                let h264_stream_index = h264_stream.index();
-
-                if let Err(_) = ffmpeg_output.write_header() {
-                    println!("Error while writing output file header");
-                }
+        // Connect to all outputs before capturing anything, this can take a while for
+        // network outputs
+        let mut opened_outputs = Vec::with_capacity(config.outputs.len());
+        for output in config.outputs.iter() {
+            match output.open() {
+                Ok(context) => opened_outputs.push((output.clone(), context)),
+                Err(e) if output.is_network() => println!("Skipping output - {}", e),
+                Err(e) => return Err(e),
+            }
+        }
 
-                ffmpeg_next::format::context::output::dump(&ffmpeg_output, 0, None);
+        let time_reference = SlickscreenTime::new(std::time::Instant::now());
+        if config.start_paused {
+            time_reference.pause();
+        }
 
-                let aac_time_base = ffmpeg_output
-                    .stream(aac_stream_index)
-                    .expect("it was just added")
-                    .time_base();
-                let h264_time_base = ffmpeg_output
-                    .stream(h264_stream_index)
-                    .expect("it was just added")
-                    .time_base();
+        let (control_sender, control_receiver) =
+            crossbeam::channel::bounded(config.packet_queue_capacity.max(1));
+        let packet_sender = PacketSender::new(PolicySender::new(
+            control_sender.clone(),
+            control_receiver.clone(),
+            config.packet_drop_policy,
+        ));
+        let preview_frame = std::sync::Arc::new(PreviewFrame::default());
+        let stats = std::sync::Arc::new(StatsCounters::default());
+        let masks = MaskList::default();
+        for mask in config.masks.iter() {
+            insert_mask(&mut masks.lock().unwrap(), mask.clone());
+        }
+        let audio_recorder = AudioRecorder::new(
+            time_reference.clone(),
+            packet_sender.clone(),
+            &config,
+            stats.clone(),
+        )?;
+        let video_recorder = match VideoRecorder::new(
+            time_reference.clone(),
+            packet_sender,
+            &config,
+            preview_frame.clone(),
+            stats.clone(),
+            masks.clone(),
+        ) {
+            Ok(video_recorder) => video_recorder,
+            Err(e) => {
+                discard_recorders(&control_sender, &control_receiver, audio_recorder, None);
+                return Err(e);
+            }
+        };
 
+        let mut streams = vec![
+            audio_recorder.stream_description.clone(),
+            video_recorder.stream_description.clone(),
+        ];
+        streams.extend(video_recorder.input_log_description.clone());
+        let mut recording_info = RecordingInfo {
+            title: config.title.clone(),
+            author: config.author.clone(),
+            hostname: gethostname::gethostname().to_string_lossy().to_string(),
+            creation_time: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+            // Filled in once the outputs that could be set up are known
+            outputs: Vec::new(),
+            display: DisplayGeometry {
+                width: video_recorder.display_width as u32,
+                height: video_recorder.display_height as u32,
+            },
+            streams: streams.iter().map(|stream| stream.info.clone()).collect(),
+        };
+        let markers = MarkerList::default();
+        let mut fan_out = FanOut::new(markers.clone());
+        for (output, context) in opened_outputs {
+            let required = !output.is_network();
+            let url = output.url();
+            match MuxerSink::new(
+                output,
+                context,
+                &streams,
+                recording_info.container_metadata(),
+            ) {
+                Ok(sink) => {
+                    fan_out.add_sink(Box::new(sink), required);
+                    recording_info.outputs.push(url);
+                }
+                Err(e) if !required => println!("Skipping output - {}", e),
+                Err(e) => {
+                    discard_recorders(
+                        &control_sender,
+                        &control_receiver,
+                        audio_recorder,
+                        Some(video_recorder),
+                    );
+                    return Err(e);
+                }
+            }
+        }
+        if fan_out.is_empty() {
+            discard_recorders(
+                &control_sender,
+                &control_receiver,
+                audio_recorder,
+                Some(video_recorder),
+            );
+            return Err(SlickscreenError::OutputError(
+                "unable to open any output".to_string(),
+            ));
+        }
+        let control = SlickscreenControl::new(
+            time_reference,
+            preview_frame,
+            fan_out.sink_states(),
+            markers,
+            masks,
+            stats,
+            video_recorder.keyframe_requested.clone(),
+        );
+
+        let worker = worker::Worker::new_consumer_with_channel(
+            control_sender,
+            control_receiver,
+            move |control_receiver: SlickscreenMessageReceiver| {
+                let mut fan_out = fan_out;
                 for msg in control_receiver.iter() {
                     match msg {
                         SlickscreenMessage::Quit => {
-                            let _ = ffmpeg_output.write_trailer();
+                            fan_out.finish();
                             return;
                         }
                         SlickscreenMessage::Audio(packet) => {
-                            let mut packet = packet;
-                            packet.rescale_ts(stream_time_base, aac_time_base);
-                            packet.set_stream(aac_stream_index);
-                            if let Err(_) = packet.write_interleaved(&mut ffmpeg_output) {
-                                println!("Error while writing audio packet");
-                            }
+                            fan_out.write_packet(StreamKind::Audio, packet);
                         }
                         SlickscreenMessage::Video(packet) => {
-                            let mut packet = packet;
-                            packet.rescale_ts(stream_time_base, h264_time_base);
-                            packet.set_stream(h264_stream_index);
-                            if let Err(_) = packet.write_interleaved(&mut ffmpeg_output) {
-                                println!("Error while writing video packet");
-                            }
+                            fan_out.write_packet(StreamKind::Video, packet);
+                        }
+                        SlickscreenMessage::Subtitle(packet) => {
+                            fan_out.write_packet(StreamKind::Subtitle, packet);
                         }
                     }
                 }
-            });
+            },
+        );
 
-        let control_sender = worker.control_sender();
         Ok(Self {
             worker,
-            audio_recorder: AudioRecorder::new(time_reference, control_sender.clone())?,
-            video_recorder: VideoRecorder::new(time_reference, control_sender.clone())?,
+            audio_recorder,
+            video_recorder,
+            control,
+            recording_info,
+            manifest_file: config.manifest_file.clone(),
         })
     }
 
-    pub fn stop(self) {
+    /// A handle for controlling the recording from other threads
+    pub fn control(&self) -> SlickscreenControl {
+        self.control.clone()
+    }
+
+    pub fn pause(&self) -> bool {
+        self.control.pause()
+    }
+
+    pub fn resume(&self) -> bool {
+        self.control.resume()
+    }
+
+    /// Mark the current point in the recording, markers become chapters in MP4 and MKV
+    pub fn add_marker(&self, label: &str) -> Marker {
+        self.control.add_marker(label)
+    }
+
+    /// Encode the next video frame as a keyframe
+    pub fn request_keyframe(&self) {
+        self.control.request_keyframe()
+    }
+
+    pub fn status(&self) -> SlickscreenStatus {
+        self.control.status()
+    }
+
+    /// Stop recording, finalize all outputs and write the manifest if one was requested
+    pub fn stop(self) -> Result<(), SlickscreenError> {
+        let status = self.control.status();
         let _ = self.audio_recorder.worker.stop();
-        let _ = self.video_recorder.worker.stop();
+        let _ = self.video_recorder.stop();
         let _ = self.worker.stop();
+
+        if let Some(manifest_file) = &self.manifest_file {
+            RecordingManifest {
+                info: self.recording_info,
+                duration_ms: status.duration_ms,
+                markers: status.markers,
+                stats: self.control.stats(),
+            }
+            .write(manifest_file)?;
+        }
+        Ok(())
     }
 }
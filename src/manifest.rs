@@ -0,0 +1,89 @@
+use super::*;
+
+use serde::Serialize;
+
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct DisplayGeometry {
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Everything known about a recording when it starts
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct RecordingInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    pub hostname: String,
+    /// ISO 8601 UTC timestamp
+    pub creation_time: String,
+    pub outputs: Vec<String>,
+    pub display: DisplayGeometry,
+    pub streams: Vec<StreamInfo>,
+}
+
+impl RecordingInfo {
+    /// A one line description of the capture settings
+    fn settings_summary(&self) -> String {
+        let streams = self
+            .streams
+            .iter()
+            .map(|stream| match stream.kind {
+                StreamKind::Video => format!(
+                    "{} {}x{}",
+                    stream.codec,
+                    stream.width.unwrap_or(0),
+                    stream.height.unwrap_or(0)
+                ),
+                StreamKind::Audio => format!(
+                    "{} {} Hz {} channels",
+                    stream.codec,
+                    stream.sample_rate.unwrap_or(0),
+                    stream.channels.unwrap_or(0)
+                ),
+                StreamKind::Subtitle => format!("{} input log", stream.codec),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            "Captured {}x{} display on {}: {}",
+            self.display.width, self.display.height, self.hostname, streams
+        )
+    }
+
+    /// Container level tags, muxers silently drop the keys they do not support
+    pub fn container_metadata(&self) -> ffmpeg_next::Dictionary {
+        let mut metadata = ffmpeg_next::Dictionary::new();
+        if let Some(title) = &self.title {
+            metadata.set("title", title);
+        }
+        if let Some(author) = &self.author {
+            metadata.set("author", author);
+            metadata.set("artist", author);
+        }
+        metadata.set("creation_time", &self.creation_time);
+        metadata.set("hostname", &self.hostname);
+        metadata.set("comment", &self.settings_summary());
+        metadata
+    }
+}
+
+/// The sidecar JSON document written next to a recording when it stops
+#[derive(Debug, Serialize)]
+pub(crate) struct RecordingManifest {
+    #[serde(flatten)]
+    pub info: RecordingInfo,
+    pub duration_ms: u64,
+    pub markers: Vec<Marker>,
+    pub stats: SlickscreenStats,
+}
+
+impl RecordingManifest {
+    pub fn write(&self, path: &str) -> Result<(), SlickscreenError> {
+        let file = std::fs::File::create(path)
+            .map_err(|e| SlickscreenError::ManifestError(format!("{}: {}", path, e)))?;
+        serde_json::to_writer_pretty(std::io::BufWriter::new(file), self)
+            .map_err(|e| SlickscreenError::ManifestError(format!("{}: {}", path, e)))
+    }
+}
@@ -0,0 +1,195 @@
+use super::*;
+
+use std::time::Duration;
+
+/// MPEG-TS packets are 188 bytes, seven of them fit in a single ethernet frame
+pub const DEFAULT_MPEGTS_PACKET_SIZE: usize = 7 * 188;
+pub const DEFAULT_SRT_LATENCY: Duration = Duration::from_millis(120);
+pub const DEFAULT_HLS_SEGMENT_DURATION: Duration = Duration::from_secs(4);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SrtMode {
+    /// Connect to a receiver that is listening on the given address
+    Caller,
+    /// Wait for a receiver to connect to the given address
+    Listener,
+}
+
+impl SrtMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SrtMode::Caller => "caller",
+            SrtMode::Listener => "listener",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum SlickscreenOutput {
+    /// Record to a local file, the container is guessed from the file extension
+    File(String),
+    /// Stream MPEG-TS over SRT to or from `host:port`
+    Srt {
+        address: String,
+        mode: SrtMode,
+        latency: Duration,
+        packet_size: usize,
+    },
+    /// Stream MPEG-TS over plain UDP to `host:port`, which may be a multicast group
+    Udp { address: String, packet_size: usize },
+    /// Stream MPEG-TS over RTP to `host:port`
+    Rtp { address: String, packet_size: usize },
+    /// Publish FLV to an RTMP server, e.g. `rtmp://host/app/stream-key`
+    Rtmp(String),
+    /// Write an HLS playlist next to its MPEG-TS segments
+    Hls {
+        playlist: String,
+        segment_duration: Duration,
+    },
+}
+
+impl SlickscreenOutput {
+    pub fn srt(address: &str, mode: SrtMode) -> Self {
+        SlickscreenOutput::Srt {
+            address: address.to_string(),
+            mode,
+            latency: DEFAULT_SRT_LATENCY,
+            packet_size: DEFAULT_MPEGTS_PACKET_SIZE,
+        }
+    }
+
+    pub fn udp(address: &str) -> Self {
+        SlickscreenOutput::Udp {
+            address: address.to_string(),
+            packet_size: DEFAULT_MPEGTS_PACKET_SIZE,
+        }
+    }
+
+    pub fn rtp(address: &str) -> Self {
+        SlickscreenOutput::Rtp {
+            address: address.to_string(),
+            packet_size: DEFAULT_MPEGTS_PACKET_SIZE,
+        }
+    }
+
+    pub fn hls(playlist: &str) -> Self {
+        SlickscreenOutput::Hls {
+            playlist: playlist.to_string(),
+            segment_duration: DEFAULT_HLS_SEGMENT_DURATION,
+        }
+    }
+
+    /// Network outputs are best effort, losing one never stops the recording
+    pub fn is_network(&self) -> bool {
+        match self {
+            SlickscreenOutput::File(_) | SlickscreenOutput::Hls { .. } => false,
+            SlickscreenOutput::Srt { .. }
+            | SlickscreenOutput::Udp { .. }
+            | SlickscreenOutput::Rtp { .. }
+            | SlickscreenOutput::Rtmp(_) => true,
+        }
+    }
+
+    /// The url handed to libavformat, protocol options are passed as query parameters
+    pub fn url(&self) -> String {
+        match self {
+            SlickscreenOutput::File(path) => path.clone(),
+            SlickscreenOutput::Srt {
+                address,
+                mode,
+                latency,
+                packet_size,
+            } => format!(
+                // libsrt expects the latency in microseconds
+                "srt://{}?mode={}&latency={}&pkt_size={}",
+                address,
+                mode.as_str(),
+                latency.as_micros(),
+                packet_size
+            ),
+            SlickscreenOutput::Udp {
+                address,
+                packet_size,
+            } => format!("udp://{}?pkt_size={}", address, packet_size),
+            SlickscreenOutput::Rtp {
+                address,
+                packet_size,
+            } => format!("rtp://{}?pkt_size={}", address, packet_size),
+            SlickscreenOutput::Rtmp(url) => url.clone(),
+            SlickscreenOutput::Hls { playlist, .. } => playlist.clone(),
+        }
+    }
+
+    /// The muxer to use, `None` lets libavformat guess it from the url
+    pub fn format_name(&self) -> Option<&'static str> {
+        match self {
+            SlickscreenOutput::File(_) => None,
+            SlickscreenOutput::Srt { .. } | SlickscreenOutput::Udp { .. } => Some("mpegts"),
+            SlickscreenOutput::Rtp { .. } => Some("rtp_mpegts"),
+            SlickscreenOutput::Rtmp(_) => Some("flv"),
+            SlickscreenOutput::Hls { .. } => Some("hls"),
+        }
+    }
+
+    /// Allocates the muxer context and opens the underlying io context.
+    ///
+    /// Note that this blocks until a receiver connects when using SRT in listener mode.
+    pub(crate) fn open(&self) -> Result<ffmpeg_next::format::context::Output, SlickscreenError> {
+        let url = self.url();
+        let output = match self.format_name() {
+            Some(format_name) => ffmpeg_next::format::output_as(&url, format_name),
+            None => ffmpeg_next::format::output(&url),
+        };
+        output.map_err(|e| SlickscreenError::OutputError(format!("{}: {}", url, e)))
+    }
+
+    /// Muxer options applied when writing the header
+    pub(crate) fn muxer_options(&self) -> ffmpeg_next::Dictionary {
+        let mut options = ffmpeg_next::Dictionary::new();
+        if self.is_network() {
+            // Hand every packet to the network as soon as it is muxed instead of
+            // waiting for the io buffer to fill up
+            options.set("flush_packets", "1");
+        }
+        if let SlickscreenOutput::Hls {
+            segment_duration, ..
+        } = self
+        {
+            options.set("hls_time", &segment_duration.as_secs_f64().to_string());
+            // Keep every segment so the playlist doubles as a recording
+            options.set("hls_list_size", "0");
+        }
+        options
+    }
+}
+
+impl std::str::FromStr for SlickscreenOutput {
+    type Err = SlickscreenError;
+
+    /// Parses `srt://`, `udp://`, `rtp://` and `rtmp://` urls with default options, a path
+    /// ending in `.m3u8` as an HLS playlist and anything else as a file path
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (scheme, address) = match s.split_once("://") {
+            Some(parts) => parts,
+            None if s.ends_with(".m3u8") => return Ok(SlickscreenOutput::hls(s)),
+            None => return Ok(SlickscreenOutput::File(s.to_string())),
+        };
+        if address.contains('?') && scheme != "rtmp" {
+            return Err(SlickscreenError::OutputError(format!(
+                "protocol options are not supported in output urls: {}",
+                s
+            )));
+        }
+        match scheme {
+            "srt" => Ok(SlickscreenOutput::srt(address, SrtMode::Caller)),
+            "udp" => Ok(SlickscreenOutput::udp(address)),
+            "rtp" => Ok(SlickscreenOutput::rtp(address)),
+            "rtmp" | "rtmps" => Ok(SlickscreenOutput::Rtmp(s.to_string())),
+            "file" => Ok(SlickscreenOutput::File(address.to_string())),
+            _ => Err(SlickscreenError::OutputError(format!(
+                "unsupported output protocol: {}",
+                scheme
+            ))),
+        }
+    }
+}
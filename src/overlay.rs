@@ -0,0 +1,906 @@
+use super::*;
+
+use ab_glyph::{Font, FontVec, PxScale, ScaleFont};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// Corner of the frame an overlay is anchored to
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OverlayPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl std::str::FromStr for OverlayPosition {
+    type Err = SlickscreenError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "top-left" => Ok(OverlayPosition::TopLeft),
+            "top-right" => Ok(OverlayPosition::TopRight),
+            "bottom-left" => Ok(OverlayPosition::BottomLeft),
+            "bottom-right" => Ok(OverlayPosition::BottomRight),
+            _ => Err(SlickscreenError::OverlayError(format!(
+                "unknown position {}, expected top-left, top-right, bottom-left or bottom-right",
+                s
+            ))),
+        }
+    }
+}
+
+impl OverlayPosition {
+    /// Top left corner of a `width` by `height` box placed `margin` pixels from the edges
+    /// of an `image_width` by `image_height` frame
+    pub(crate) fn place(
+        &self,
+        width: i64,
+        height: i64,
+        image_width: i64,
+        image_height: i64,
+        margin: i64,
+    ) -> (i64, i64) {
+        let left = margin;
+        let right = image_width - width - margin;
+        let top = margin;
+        let bottom = image_height - height - margin;
+        match self {
+            OverlayPosition::TopLeft => (left, top),
+            OverlayPosition::TopRight => (right, top),
+            OverlayPosition::BottomLeft => (left, bottom),
+            OverlayPosition::BottomRight => (right, bottom),
+        }
+    }
+}
+
+/// A line of text drawn onto every frame, e.g. a wall clock timestamp for compliance
+/// recordings
+#[derive(Clone, Debug)]
+pub struct TextOverlayConfig {
+    /// strftime style format of the local time, `{hostname}` is replaced by the host name
+    pub format: String,
+    /// TrueType or OpenType font
+    pub font_path: std::path::PathBuf,
+    /// Height of the text in pixels
+    pub font_size: f32,
+    pub position: OverlayPosition,
+    /// Draw a translucent box behind the text so it stays readable on any content
+    pub background: bool,
+}
+
+impl TextOverlayConfig {
+    pub const DEFAULT_FORMAT: &'static str = "%Y-%m-%d %H:%M:%S %:z {hostname}";
+}
+
+/// How a masked region is hidden
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MaskStyle {
+    /// Blurred enough to make text unreadable while keeping the layout recognizable
+    Blur,
+    /// Filled with solid black
+    Black,
+}
+
+impl Default for MaskStyle {
+    fn default() -> Self {
+        MaskStyle::Black
+    }
+}
+
+impl std::str::FromStr for MaskStyle {
+    type Err = SlickscreenError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "blur" => Ok(MaskStyle::Blur),
+            "black" => Ok(MaskStyle::Black),
+            _ => Err(SlickscreenError::OverlayError(format!(
+                "unknown mask style {}, expected blur or black",
+                s
+            ))),
+        }
+    }
+}
+
+fn default_mask_enabled() -> bool {
+    true
+}
+
+/// A rectangle of the screen hidden from the recording, e.g. a password manager or a
+/// chat window
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MaskRegion {
+    /// Identifies the mask in the control api, unnamed masks are numbered when added
+    #[serde(default)]
+    pub name: String,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    #[serde(default)]
+    pub style: MaskStyle,
+    /// Disabled masks are kept so they can be turned back on
+    #[serde(default = "default_mask_enabled")]
+    pub enabled: bool,
+}
+
+impl std::str::FromStr for MaskRegion {
+    type Err = SlickscreenError;
+
+    /// Parses `[NAME=]X,Y,WIDTHxHEIGHT[:blur|black]`, e.g. `chat=1280,0,640x1080:blur`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            SlickscreenError::OverlayError(format!(
+                "invalid mask {}, expected [NAME=]X,Y,WIDTHxHEIGHT[:blur|black]",
+                s
+            ))
+        };
+        let (name, rest) = match s.split_once('=') {
+            Some((name, rest)) => (name, rest),
+            None => ("", s),
+        };
+        let (rectangle, style) = match rest.split_once(':') {
+            Some((rectangle, style)) => (rectangle, style.parse()?),
+            None => (rest, MaskStyle::default()),
+        };
+        let mut parts = rectangle.split(',');
+        let (x, y, size) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(x), Some(y), Some(size), None) => (x, y, size),
+            _ => return Err(invalid()),
+        };
+        let (width, height) = size.split_once('x').ok_or_else(invalid)?;
+        let number = |value: &str| value.trim().parse::<u32>().map_err(|_| invalid());
+        Ok(MaskRegion {
+            name: name.to_string(),
+            x: number(x)?,
+            y: number(y)?,
+            width: number(width)?,
+            height: number(height)?,
+            style,
+            enabled: true,
+        })
+    }
+}
+
+pub(crate) type MaskList = Arc<Mutex<Vec<MaskRegion>>>;
+
+/// Add `region` to `masks`, replacing the mask of the same name. Unnamed regions get the
+/// first free name of the form `mask1`
+pub(crate) fn insert_mask(masks: &mut Vec<MaskRegion>, mut region: MaskRegion) -> MaskRegion {
+    if region.name.is_empty() {
+        region.name = (1..)
+            .map(|index| format!("mask{}", index))
+            .find(|name| !masks.iter().any(|mask| mask.name == *name))
+            .expect("there is always a free name");
+    }
+    match masks.iter_mut().find(|mask| mask.name == region.name) {
+        Some(mask) => *mask = region.clone(),
+        None => masks.push(region.clone()),
+    }
+    region
+}
+
+/// Something drawn onto every captured frame before it is previewed and encoded
+pub(crate) trait Overlay: Send {
+    /// Called for every input event before the frame it happened in is drawn
+    fn input_event(&mut self, _event: &InputEvent, _pts: i64) {}
+
+    /// Draw onto `image`, `pts` is the timestamp of the frame in microseconds
+    fn draw(&mut self, image: &mut BgraImage, pts: i64);
+
+    /// False if `draw` would leave the frame at `pts` untouched, the capture can then
+    /// skip copying the screen into a frame to draw on
+    fn draws_at(&self, _pts: i64) -> bool {
+        true
+    }
+}
+
+/// A mutable view of a BGRA plane
+pub(crate) struct BgraImage<'a> {
+    data: &'a mut [u8],
+    stride: usize,
+    width: usize,
+    height: usize,
+}
+
+impl<'a> BgraImage<'a> {
+    pub fn new(data: &'a mut [u8], stride: usize, width: usize, height: usize) -> Self {
+        assert!(stride >= width * 4);
+        assert!(height == 0 || data.len() >= (height - 1) * stride + width * 4);
+        Self {
+            data,
+            stride,
+            width,
+            height,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Blend a pixel whose color is premultiplied by its alpha, coordinates outside the
+    /// image are ignored
+    #[inline]
+    pub fn blend_premultiplied(&mut self, x: i64, y: i64, bgra: [u8; 4]) {
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            return;
+        }
+        let alpha = bgra[3] as u32;
+        if alpha == 0 {
+            return;
+        }
+        let offset = y as usize * self.stride + x as usize * 4;
+        let pixel = &mut self.data[offset..offset + 4];
+        for (destination, source) in pixel.iter_mut().zip(bgra).take(3) {
+            let blended = source as u32 + (*destination as u32 * (255 - alpha) + 127) / 255;
+            *destination = blended.min(255) as u8;
+        }
+        pixel[3] = 255;
+    }
+
+    /// Blend a pixel with a straight, not premultiplied, alpha
+    #[inline]
+    pub fn blend(&mut self, x: i64, y: i64, bgra: [u8; 4]) {
+        let alpha = bgra[3] as u32;
+        let premultiply = |channel: u8| ((channel as u32 * alpha + 127) / 255) as u8;
+        self.blend_premultiplied(
+            x,
+            y,
+            [
+                premultiply(bgra[0]),
+                premultiply(bgra[1]),
+                premultiply(bgra[2]),
+                bgra[3],
+            ],
+        );
+    }
+
+    /// Blend `bgra` wherever `mask` covers the image, with the mask's top left corner at
+    /// `x`, `y`
+    pub fn blend_mask(&mut self, x: i64, y: i64, mask: &AlphaMask, bgra: [u8; 4]) {
+        for row in 0..mask.height {
+            for column in 0..mask.width {
+                let coverage = mask.coverage[(row * mask.width + column) as usize] as u32;
+                if coverage > 0 {
+                    let alpha = (bgra[3] as u32 * coverage / 255) as u8;
+                    self.blend(x + column, y + row, [bgra[0], bgra[1], bgra[2], alpha]);
+                }
+            }
+        }
+    }
+
+    /// Box blur a rectangle in place, clipped to the image. Pixels outside the rectangle
+    /// do not bleed into it
+    pub fn blur_rect(&mut self, x: i64, y: i64, width: i64, height: i64, radius: usize) {
+        let left = x.clamp(0, self.width as i64) as usize;
+        let right = (x + width).clamp(0, self.width as i64) as usize;
+        let top = y.clamp(0, self.height as i64) as usize;
+        let bottom = (y + height).clamp(0, self.height as i64) as usize;
+        if left >= right || top >= bottom || radius == 0 {
+            return;
+        }
+
+        let mut line = Vec::with_capacity((right - left).max(bottom - top));
+        // Two passes of a box blur are close enough to a gaussian blur
+        for _ in 0..2 {
+            for row in top..bottom {
+                let start = row * self.stride + left * 4;
+                box_blur_line(self.data, start, 4, right - left, radius, &mut line);
+            }
+            for column in left..right {
+                let start = top * self.stride + column * 4;
+                box_blur_line(
+                    self.data,
+                    start,
+                    self.stride,
+                    bottom - top,
+                    radius,
+                    &mut line,
+                );
+            }
+        }
+    }
+
+    /// Blend a rectangle of a single color, clipped to the image
+    pub fn fill_rect(&mut self, x: i64, y: i64, width: i64, height: i64, bgra: [u8; 4]) {
+        for row in y.max(0)..(y + height).min(self.height as i64) {
+            for column in x.max(0)..(x + width).min(self.width as i64) {
+                self.blend(column, row, bgra);
+            }
+        }
+    }
+}
+
+/// Replace `count` pixels, `step` bytes apart from `start`, by the average of the pixels
+/// within `radius` of them. The line's edge pixels are repeated past its ends
+fn box_blur_line(
+    data: &mut [u8],
+    start: usize,
+    step: usize,
+    count: usize,
+    radius: usize,
+    line: &mut Vec<[u8; 4]>,
+) {
+    line.clear();
+    line.extend((0..count).map(|index| {
+        let offset = start + index * step;
+        [
+            data[offset],
+            data[offset + 1],
+            data[offset + 2],
+            data[offset + 3],
+        ]
+    }));
+    let pixel = |index: i64| line[index.clamp(0, count as i64 - 1) as usize];
+
+    let radius = radius as i64;
+    let window = 2 * radius as u32 + 1;
+    let mut sum = [0u32; 4];
+    for index in -radius..=radius {
+        for (total, channel) in sum.iter_mut().zip(pixel(index)) {
+            *total += channel as u32;
+        }
+    }
+    for index in 0..count {
+        let offset = start + index * step;
+        for (destination, total) in data[offset..offset + 4].iter_mut().zip(sum) {
+            *destination = ((total + window / 2) / window) as u8;
+        }
+        let entering = pixel(index as i64 + radius + 1);
+        let leaving = pixel(index as i64 - radius);
+        for ((total, added), removed) in sum.iter_mut().zip(entering).zip(leaving) {
+            *total = *total + added as u32 - removed as u32;
+        }
+    }
+}
+
+/// Coverage of a rasterized shape, 255 is fully covered
+pub(crate) struct AlphaMask {
+    width: i64,
+    height: i64,
+    coverage: Vec<u8>,
+}
+
+/// Renders single lines of text with a TrueType or OpenType font
+pub(crate) struct TextRenderer {
+    font: FontVec,
+    scale: PxScale,
+}
+
+impl TextRenderer {
+    /// `size` is the line height in pixels
+    pub fn load(path: &std::path::Path, size: f32) -> Result<Self, SlickscreenError> {
+        let data = std::fs::read(path).map_err(|e| {
+            SlickscreenError::OverlayError(format!("{}: {}", path.to_string_lossy(), e))
+        })?;
+        Self::from_data(data, size).map_err(|e| {
+            SlickscreenError::OverlayError(format!("{}: {}", path.to_string_lossy(), e))
+        })
+    }
+
+    /// A font file that is already in memory
+    fn from_data(data: Vec<u8>, size: f32) -> Result<Self, ab_glyph::InvalidFont> {
+        Ok(Self {
+            font: FontVec::try_from_vec(data)?,
+            scale: PxScale::from(size),
+        })
+    }
+
+    /// Width and height of `text` in pixels
+    pub fn measure(&self, text: &str) -> (i64, i64) {
+        let font = self.font.as_scaled(self.scale);
+        let mut width = 0.0;
+        let mut previous = None;
+        for character in text.chars() {
+            let glyph_id = font.glyph_id(character);
+            if let Some(previous) = previous {
+                width += font.kern(previous, glyph_id);
+            }
+            width += font.h_advance(glyph_id);
+            previous = Some(glyph_id);
+        }
+        (width.ceil() as i64, font.height().ceil() as i64)
+    }
+
+    /// Coverage of every pixel of `text` within its `measure` box
+    pub fn rasterize(&self, text: &str) -> AlphaMask {
+        let (width, height) = self.measure(text);
+        let mut mask = AlphaMask {
+            width,
+            height,
+            coverage: vec![0; (width * height) as usize],
+        };
+        self.for_each_pixel(0, 0, text, |x, y, coverage| {
+            if x >= 0 && y >= 0 && x < width && y < height {
+                let pixel = &mut mask.coverage[(y * width + x) as usize];
+                *pixel = (*pixel).max((coverage * 255.0) as u8);
+            }
+        });
+        mask
+    }
+
+    /// Draw `text` with its top left corner at `x`, `y`
+    pub fn draw(&self, image: &mut BgraImage, x: i64, y: i64, text: &str, bgra: [u8; 4]) {
+        self.for_each_pixel(x, y, text, |x, y, coverage| {
+            image.blend(
+                x,
+                y,
+                [bgra[0], bgra[1], bgra[2], (bgra[3] as f32 * coverage) as u8],
+            );
+        });
+    }
+
+    fn for_each_pixel(&self, x: i64, y: i64, text: &str, mut f: impl FnMut(i64, i64, f32)) {
+        let font = self.font.as_scaled(self.scale);
+        let mut caret = ab_glyph::point(x as f32, y as f32 + font.ascent());
+        let mut previous = None;
+        for character in text.chars() {
+            let glyph_id = font.glyph_id(character);
+            if let Some(previous) = previous {
+                caret.x += font.kern(previous, glyph_id);
+            }
+            previous = Some(glyph_id);
+            let glyph = glyph_id.with_scale_and_position(self.scale, caret);
+            caret.x += font.h_advance(glyph_id);
+
+            if let Some(outline) = self.font.outline_glyph(glyph) {
+                let bounds = outline.px_bounds();
+                outline.draw(|glyph_x, glyph_y, coverage| {
+                    f(
+                        bounds.min.x as i64 + glyph_x as i64,
+                        bounds.min.y as i64 + glyph_y as i64,
+                        coverage,
+                    );
+                });
+            }
+        }
+    }
+}
+
+/// How long a click ring takes to expand and fade out
+const CLICK_RING_DURATION_US: i64 = 500_000;
+const CLICK_RING_MIN_RADIUS: f32 = 8.0;
+const CLICK_RING_MAX_RADIUS: f32 = 40.0;
+const CLICK_RING_THICKNESS: f32 = 4.0;
+
+/// Draws an expanding ring wherever a mouse button was pressed
+pub(crate) struct ClickOverlay {
+    // (x, y, button, pts of the click)
+    clicks: Vec<(i64, i64, u32, i64)>,
+}
+
+impl ClickOverlay {
+    pub fn new() -> Self {
+        Self { clicks: Vec::new() }
+    }
+
+    fn ring_color(button: u32) -> [u8; 3] {
+        match button {
+            // Secondary clicks are blue, everything else amber
+            3 => [255, 160, 40],
+            _ => [0, 190, 255],
+        }
+    }
+}
+
+impl Overlay for ClickOverlay {
+    fn input_event(&mut self, event: &InputEvent, pts: i64) {
+        match *event {
+            // Buttons 4 to 7 are the scroll wheels
+            InputEvent::ButtonPress { x, y, button } if button <= 3 => {
+                self.clicks.push((x, y, button, pts))
+            }
+            _ => {}
+        }
+    }
+
+    fn draw(&mut self, image: &mut BgraImage, pts: i64) {
+        self.clicks
+            .retain(|&(_, _, _, click_pts)| pts - click_pts < CLICK_RING_DURATION_US);
+
+        for &(x, y, button, click_pts) in self.clicks.iter() {
+            let progress = (pts - click_pts).max(0) as f32 / CLICK_RING_DURATION_US as f32;
+            let radius =
+                CLICK_RING_MIN_RADIUS + (CLICK_RING_MAX_RADIUS - CLICK_RING_MIN_RADIUS) * progress;
+            let alpha = 255.0 * (1.0 - progress);
+            let [blue, green, red] = Self::ring_color(button);
+
+            let extent = (radius + CLICK_RING_THICKNESS).ceil() as i64;
+            for row in -extent..=extent {
+                for column in -extent..=extent {
+                    let distance = ((row * row + column * column) as f32).sqrt();
+                    // Antialiased edges from the distance to the middle of the ring
+                    let coverage = (CLICK_RING_THICKNESS / 2.0 - (distance - radius).abs() + 0.5)
+                        .clamp(0.0, 1.0);
+                    if coverage > 0.0 {
+                        image.blend(
+                            x + column,
+                            y + row,
+                            [blue, green, red, (alpha * coverage) as u8],
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// How long the caption stays up after the last key press
+const KEYSTROKE_CAPTION_DURATION_US: i64 = 2_000_000;
+const KEYSTROKE_CAPTION_MAX_KEYS: usize = 12;
+
+/// Shows the most recent key presses as a caption at the bottom of the frame
+pub(crate) struct KeystrokeOverlay {
+    text: TextRenderer,
+    keys: VecDeque<String>,
+    last_key_pts: i64,
+}
+
+impl KeystrokeOverlay {
+    pub fn new(text: TextRenderer) -> Self {
+        Self {
+            text,
+            keys: VecDeque::with_capacity(KEYSTROKE_CAPTION_MAX_KEYS),
+            last_key_pts: 0,
+        }
+    }
+}
+
+impl Overlay for KeystrokeOverlay {
+    fn input_event(&mut self, event: &InputEvent, pts: i64) {
+        if let InputEvent::KeyPress { key } = event {
+            if self.keys.len() == KEYSTROKE_CAPTION_MAX_KEYS {
+                self.keys.pop_front();
+            }
+            self.keys.push_back(key.clone());
+            self.last_key_pts = pts;
+        }
+    }
+
+    fn draw(&mut self, image: &mut BgraImage, pts: i64) {
+        if pts - self.last_key_pts >= KEYSTROKE_CAPTION_DURATION_US {
+            self.keys.clear();
+        }
+        if self.keys.is_empty() {
+            return;
+        }
+
+        let caption = self.keys.iter().cloned().collect::<Vec<_>>().join(" ");
+        let (width, height) = self.text.measure(&caption);
+        let padding = height / 3;
+        let x = (image.width() as i64 - width) / 2;
+        let y = image.height() as i64 - height * 3;
+        image.fill_rect(
+            x - padding,
+            y - padding,
+            width + padding * 2,
+            height + padding * 2,
+            [0, 0, 0, 170],
+        );
+        self.text.draw(image, x, y, &caption, [255, 255, 255, 255]);
+    }
+}
+
+/// Substitute `{hostname}` into a strftime style format and check the result, formatting
+/// panics on invalid specifiers
+fn expand_time_format(format: &str, hostname: &str) -> Result<String, SlickscreenError> {
+    // A literal % in the host name must not be read as a format specifier
+    let expanded = format.replace("{hostname}", &hostname.replace('%', "%%"));
+    if chrono::format::StrftimeItems::new(&expanded)
+        .any(|item| matches!(item, chrono::format::Item::Error))
+    {
+        return Err(SlickscreenError::OverlayError(format!(
+            "invalid time format {}",
+            format
+        )));
+    }
+    Ok(expanded)
+}
+
+/// Draws the local time and other text onto every frame
+pub(crate) struct TextOverlay {
+    text: TextRenderer,
+    format: String,
+    position: OverlayPosition,
+    background: bool,
+    /// The text is only rasterized again when it changes, usually once a second
+    rendered: Option<(String, AlphaMask)>,
+}
+
+impl TextOverlay {
+    pub fn new(config: &TextOverlayConfig) -> Result<Self, SlickscreenError> {
+        let format = expand_time_format(
+            &config.format,
+            &gethostname::gethostname().to_string_lossy(),
+        )?;
+        Ok(Self {
+            text: TextRenderer::load(&config.font_path, config.font_size)?,
+            format,
+            position: config.position,
+            background: config.background,
+            rendered: None,
+        })
+    }
+}
+
+impl Overlay for TextOverlay {
+    fn draw(&mut self, image: &mut BgraImage, _pts: i64) {
+        let text = chrono::Local::now().format(&self.format).to_string();
+        if self
+            .rendered
+            .as_ref()
+            .map_or(true, |(rendered, _)| *rendered != text)
+        {
+            let mask = self.text.rasterize(&text);
+            self.rendered = Some((text, mask));
+        }
+        let mask = match &self.rendered {
+            Some((_, mask)) => mask,
+            None => return,
+        };
+
+        let padding = mask.height / 4;
+        let (x, y) = self.position.place(
+            mask.width,
+            mask.height,
+            image.width() as i64,
+            image.height() as i64,
+            padding * 3,
+        );
+        if self.background {
+            image.fill_rect(
+                x - padding,
+                y - padding,
+                mask.width + padding * 2,
+                mask.height + padding * 2,
+                [0, 0, 0, 160],
+            );
+        }
+        image.blend_mask(x, y, mask, [255, 255, 255, 255]);
+    }
+}
+
+const MASK_BLUR_RADIUS: usize = 16;
+
+/// Hides the mask regions, which can change while recording. Runs before every other
+/// overlay so only the screen content is hidden
+pub(crate) struct MaskOverlay {
+    masks: MaskList,
+}
+
+impl MaskOverlay {
+    pub fn new(masks: MaskList) -> Self {
+        Self { masks }
+    }
+}
+
+impl Overlay for MaskOverlay {
+    fn draws_at(&self, _pts: i64) -> bool {
+        self.masks.lock().unwrap().iter().any(|mask| mask.enabled)
+    }
+
+    fn draw(&mut self, image: &mut BgraImage, _pts: i64) {
+        let masks = self.masks.lock().unwrap();
+        for mask in masks.iter().filter(|mask| mask.enabled) {
+            let (x, y) = (mask.x as i64, mask.y as i64);
+            let (width, height) = (mask.width as i64, mask.height as i64);
+            match mask.style {
+                MaskStyle::Blur => image.blur_rect(x, y, width, height, MASK_BLUR_RADIUS),
+                MaskStyle::Black => image.fill_rect(x, y, width, height, [0, 0, 0, 255]),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 320;
+    const HEIGHT: usize = 240;
+
+    /// Feed `events` to `overlay` through a channel input source, the way the capture
+    /// thread does
+    fn send_events(overlay: &mut dyn Overlay, pts: i64, events: Vec<InputEvent>) {
+        let (sender, receiver) = crossbeam::channel::unbounded();
+        let mut source = InputSource::Channel(receiver).open().unwrap();
+        events
+            .into_iter()
+            .for_each(|event| sender.send(event).unwrap());
+        let mut polled = Vec::new();
+        source.poll(&mut polled);
+        for event in polled.iter() {
+            overlay.input_event(event, pts);
+        }
+    }
+
+    /// Draw onto a transparent frame, every pixel that was drawn ends up opaque
+    fn draw(overlay: &mut dyn Overlay, pts: i64) -> Vec<u8> {
+        let mut data = vec![0; WIDTH * HEIGHT * 4];
+        overlay.draw(
+            &mut BgraImage::new(&mut data, WIDTH * 4, WIDTH, HEIGHT),
+            pts,
+        );
+        data
+    }
+
+    fn pixel(data: &[u8], x: usize, y: usize) -> [u8; 4] {
+        let offset = (y * WIDTH + x) * 4;
+        data[offset..offset + 4].try_into().unwrap()
+    }
+
+    fn drawn_pixels(data: &[u8]) -> usize {
+        data.chunks_exact(4).filter(|pixel| pixel[3] != 0).count()
+    }
+
+    #[test]
+    fn click_draws_a_fading_ring() {
+        let mut overlay = ClickOverlay::new();
+        send_events(
+            &mut overlay,
+            1_000_000,
+            vec![InputEvent::ButtonPress {
+                x: 100,
+                y: 100,
+                button: 1,
+            }],
+        );
+
+        let data = draw(&mut overlay, 1_000_000);
+        let [blue, green, red, alpha] = pixel(&data, 100 + CLICK_RING_MIN_RADIUS as usize, 100);
+        assert_eq!((blue, alpha), (0, 255));
+        assert!(green > 150 && red > 200);
+        // The ring is hollow
+        assert_eq!(pixel(&data, 100, 100), [0; 4]);
+
+        // Expanded and fainter halfway through
+        let data = draw(&mut overlay, 1_000_000 + CLICK_RING_DURATION_US / 2);
+        let radius = (CLICK_RING_MIN_RADIUS + CLICK_RING_MAX_RADIUS) / 2.0;
+        let [_, _, red, _] = pixel(&data, 100 + radius as usize, 100);
+        assert!(red > 64 && red < 192);
+        assert_eq!(
+            pixel(&data, 100 + CLICK_RING_MIN_RADIUS as usize, 100),
+            [0; 4]
+        );
+
+        // Gone once the animation is over
+        let data = draw(&mut overlay, 1_000_000 + CLICK_RING_DURATION_US);
+        assert_eq!(drawn_pixels(&data), 0);
+        assert!(overlay.clicks.is_empty());
+    }
+
+    #[test]
+    fn secondary_click_is_blue() {
+        let mut overlay = ClickOverlay::new();
+        let press = InputEvent::ButtonPress {
+            x: 100,
+            y: 100,
+            button: 3,
+        };
+        send_events(&mut overlay, 0, vec![press]);
+        let [blue, _, red, _] = pixel(&draw(&mut overlay, 0), 108, 100);
+        assert!(blue > 200 && red < 64);
+    }
+
+    #[test]
+    fn scrolling_is_not_a_click() {
+        let mut overlay = ClickOverlay::new();
+        let scrolls = (4..=7)
+            .map(|button| InputEvent::ButtonPress {
+                x: 100,
+                y: 100,
+                button,
+            })
+            .collect();
+        send_events(&mut overlay, 0, scrolls);
+        assert_eq!(drawn_pixels(&draw(&mut overlay, 0)), 0);
+    }
+
+    /// Printable ASCII drawn as filled boxes, made by tests/fixtures/make_box_font.py
+    fn test_font() -> TextRenderer {
+        let data = include_bytes!("../tests/fixtures/box_font.ttf");
+        TextRenderer::from_data(data.to_vec(), 24.0).unwrap()
+    }
+
+    fn key(key: &str) -> InputEvent {
+        InputEvent::KeyPress {
+            key: key.to_string(),
+        }
+    }
+
+    #[test]
+    fn keystroke_caption_expires() {
+        let mut overlay = KeystrokeOverlay::new(test_font());
+        assert_eq!(drawn_pixels(&draw(&mut overlay, 0)), 0);
+
+        send_events(&mut overlay, 1_000_000, vec![key("Ctrl"), key("c")]);
+        let data = draw(&mut overlay, 1_000_000);
+        // White text on a dark box in the bottom part of the frame
+        assert_eq!(drawn_pixels(&data[..WIDTH * HEIGHT * 2]), 0);
+        assert!(data
+            .chunks_exact(4)
+            .any(|pixel| pixel[0] > 200 && pixel[1] > 200 && pixel[2] > 200));
+
+        // Another key press keeps the caption up for longer
+        send_events(&mut overlay, 2_500_000, vec![key("v")]);
+        let before_expiry = 2_500_000 + KEYSTROKE_CAPTION_DURATION_US - 1;
+        let wider = draw(&mut overlay, before_expiry);
+        assert!(drawn_pixels(&wider) > drawn_pixels(&data));
+
+        assert_eq!(drawn_pixels(&draw(&mut overlay, before_expiry + 1)), 0);
+        assert!(overlay.keys.is_empty());
+    }
+
+    #[test]
+    fn keystroke_caption_keeps_the_latest_keys() {
+        let mut overlay = KeystrokeOverlay::new(test_font());
+        let keys = (0..20).map(|i| key(&i.to_string())).collect();
+        send_events(&mut overlay, 0, keys);
+        assert_eq!(overlay.keys.len(), KEYSTROKE_CAPTION_MAX_KEYS);
+        assert_eq!(overlay.keys.front().unwrap(), "8");
+        assert_eq!(overlay.keys.back().unwrap(), "19");
+    }
+
+    #[test]
+    fn mask_overlay_only_draws_enabled_masks() {
+        let masks = MaskList::default();
+        let overlay = MaskOverlay::new(masks.clone());
+        assert!(!overlay.draws_at(0));
+
+        let mut region: MaskRegion = "10,10,20x20".parse().unwrap();
+        region.enabled = false;
+        let mut region = insert_mask(&mut masks.lock().unwrap(), region);
+        assert!(!overlay.draws_at(0));
+
+        region.enabled = true;
+        insert_mask(&mut masks.lock().unwrap(), region);
+        assert!(overlay.draws_at(0));
+    }
+
+    fn text_config(format: &str) -> TextOverlayConfig {
+        TextOverlayConfig {
+            format: format.to_string(),
+            font_path: concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/box_font.ttf").into(),
+            font_size: 24.0,
+            position: OverlayPosition::TopLeft,
+            background: true,
+        }
+    }
+
+    #[test]
+    fn text_overlay_rejects_invalid_time_formats() {
+        for format in ["%Y %Q", "%H:%M %", "{hostname} %:"] {
+            assert!(
+                matches!(
+                    TextOverlay::new(&text_config(format)),
+                    Err(SlickscreenError::OverlayError(_))
+                ),
+                "{}",
+                format
+            );
+        }
+
+        let mut overlay =
+            TextOverlay::new(&text_config(TextOverlayConfig::DEFAULT_FORMAT)).unwrap();
+        assert!(drawn_pixels(&draw(&mut overlay, 0)) > 0);
+    }
+
+    #[test]
+    fn percent_in_the_host_name_is_escaped() {
+        let format = expand_time_format("{hostname} %H", "build%dhost").unwrap();
+        assert_eq!(format, "build%%dhost %H");
+        let time = chrono::NaiveDate::from_ymd_opt(2022, 5, 1)
+            .unwrap()
+            .and_hms_opt(13, 0, 0)
+            .unwrap();
+        assert_eq!(time.format(&format).to_string(), "build%dhost 13");
+    }
+}
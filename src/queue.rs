@@ -0,0 +1,226 @@
+use super::*;
+
+use crossbeam::channel::{Receiver, SendError, Sender, TrySendError};
+
+/// What happens to new items when a bounded queue between two threads is full
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Discard the oldest queued item to make room, keeps latency low
+    DropOldest,
+    /// Discard the new item
+    DropNewest,
+    /// Wait for room, nothing is lost but the producer stalls
+    Block,
+}
+
+impl std::str::FromStr for DropPolicy {
+    type Err = SlickscreenError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop-oldest" => Ok(DropPolicy::DropOldest),
+            "drop-newest" => Ok(DropPolicy::DropNewest),
+            "block" => Ok(DropPolicy::Block),
+            _ => Err(SlickscreenError::InvalidEncoderConfig(format!(
+                "unknown drop policy {}, expected drop-oldest, drop-newest or block",
+                s
+            ))),
+        }
+    }
+}
+
+/// The sending end of a bounded channel that applies a `DropPolicy` when the channel is full
+pub(crate) struct PolicySender<T> {
+    sender: Sender<T>,
+    // Only used to discard the oldest item
+    receiver: Receiver<T>,
+    policy: DropPolicy,
+}
+
+impl<T> Clone for PolicySender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            receiver: self.receiver.clone(),
+            policy: self.policy,
+        }
+    }
+}
+
+impl<T> PolicySender<T> {
+    /// `receiver` must belong to the same channel as `sender`
+    pub fn new(sender: Sender<T>, receiver: Receiver<T>, policy: DropPolicy) -> Self {
+        Self {
+            sender,
+            receiver,
+            policy,
+        }
+    }
+
+    /// Queue `item` according to the policy, returning how many items were dropped
+    pub fn send(&self, item: T) -> Result<u64, SendError<T>> {
+        self.send_with(item, |_, _| {})
+    }
+
+    /// Like `send`, but every dropped item is handed to `on_drop` so its resources can be
+    /// reclaimed. When an older item makes room for `item`, `on_drop` also gets `item` so
+    /// state of the dropped item can be carried forward
+    pub fn send_with(
+        &self,
+        item: T,
+        mut on_drop: impl FnMut(T, Option<&mut T>),
+    ) -> Result<u64, SendError<T>> {
+        match self.policy {
+            DropPolicy::Block => self.sender.send(item).map(|_| 0),
+            DropPolicy::DropNewest => match self.sender.try_send(item) {
+                Ok(()) => Ok(0),
+                Err(TrySendError::Full(item)) => {
+                    on_drop(item, None);
+                    Ok(1)
+                }
+                Err(TrySendError::Disconnected(item)) => Err(SendError(item)),
+            },
+            DropPolicy::DropOldest => {
+                let mut item = item;
+                let mut dropped = 0;
+                loop {
+                    match self.sender.try_send(item) {
+                        Ok(()) => return Ok(dropped),
+                        Err(TrySendError::Full(rejected)) => {
+                            item = rejected;
+                            // The consumer may have taken an item in the meantime
+                            if let Ok(oldest) = self.receiver.try_recv() {
+                                on_drop(oldest, Some(&mut item));
+                                dropped += 1;
+                            }
+                        }
+                        Err(TrySendError::Disconnected(item)) => return Err(SendError(item)),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Queue `item` without ever blocking, dropping it if the channel is full. This is the
+    /// only safe way to send from real-time threads such as audio callbacks.
+    pub fn try_send(&self, item: T) -> Result<u64, SendError<T>> {
+        match self.sender.try_send(item) {
+            Ok(()) => Ok(0),
+            Err(TrySendError::Full(_)) => Ok(1),
+            Err(TrySendError::Disconnected(item)) => Err(SendError(item)),
+        }
+    }
+}
+
+/// Queues encoded packets for the muxer thread. Any producer may evict a video packet,
+/// the video encoder then has to start over with a keyframe so the stream stays decodable
+#[derive(Clone)]
+pub(crate) struct PacketSender {
+    sender: PolicySender<SlickscreenMessage>,
+    video_dropped: std::sync::Arc<std::sync::atomic::AtomicBool>,
+}
+
+impl PacketSender {
+    pub fn new(sender: PolicySender<SlickscreenMessage>) -> Self {
+        Self {
+            sender,
+            video_dropped: Default::default(),
+        }
+    }
+
+    /// Queue `message` according to the policy, returning how many messages were dropped
+    pub fn send(&self, message: SlickscreenMessage) -> Result<u64, SendError<SlickscreenMessage>> {
+        self.sender.send_with(message, |dropped, _| {
+            if let SlickscreenMessage::Video(_) = dropped {
+                self.video_dropped
+                    .store(true, std::sync::atomic::Ordering::Relaxed);
+            }
+        })
+    }
+
+    /// True if a video packet was dropped since the last call
+    pub fn take_video_dropped(&self) -> bool {
+        self.video_dropped
+            .swap(false, std::sync::atomic::Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(capacity: usize, policy: DropPolicy) -> (PolicySender<u32>, Receiver<u32>) {
+        let (sender, receiver) = crossbeam::channel::bounded(capacity);
+        (
+            PolicySender::new(sender, receiver.clone(), policy),
+            receiver,
+        )
+    }
+
+    #[test]
+    fn drop_oldest_hands_back_evicted_items() {
+        let (sender, receiver) = queue(2, DropPolicy::DropOldest);
+        let mut dropped = Vec::new();
+        for item in 0..5 {
+            sender.send_with(item, |old, _| dropped.push(old)).unwrap();
+        }
+        assert_eq!(dropped, vec![0, 1, 2]);
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![3, 4]);
+    }
+
+    #[test]
+    fn drop_oldest_can_update_the_queued_item() {
+        let (sender, receiver) = queue(1, DropPolicy::DropOldest);
+        sender.send(1).unwrap();
+        sender
+            .send_with(2, |old, queued| *queued.unwrap() += old * 10)
+            .unwrap();
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![12]);
+    }
+
+    #[test]
+    fn drop_newest_hands_back_rejected_items() {
+        let (sender, receiver) = queue(2, DropPolicy::DropNewest);
+        let mut dropped = Vec::new();
+        for item in 0..5 {
+            let count = sender
+                .send_with(item, |new, queued| {
+                    assert!(queued.is_none());
+                    dropped.push(new)
+                })
+                .unwrap();
+            assert_eq!(count, if item < 2 { 0 } else { 1 });
+        }
+        assert_eq!(dropped, vec![2, 3, 4]);
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![0, 1]);
+    }
+
+    #[test]
+    fn evicted_video_packets_are_reported_to_the_video_encoder() {
+        let (sender, receiver) = crossbeam::channel::bounded(2);
+        let packets = PacketSender::new(PolicySender::new(
+            sender,
+            receiver.clone(),
+            DropPolicy::DropOldest,
+        ));
+        let packet = ffmpeg_next::Packet::empty;
+        packets.send(SlickscreenMessage::Video(packet())).unwrap();
+        packets.send(SlickscreenMessage::Audio(packet())).unwrap();
+        assert!(!packets.take_video_dropped());
+
+        // Audio evicts the queued video packet
+        assert_eq!(
+            packets.send(SlickscreenMessage::Audio(packet())).unwrap(),
+            1
+        );
+        assert!(packets.take_video_dropped());
+        assert!(!packets.take_video_dropped());
+
+        // Only audio is left to evict
+        packets
+            .send(SlickscreenMessage::Subtitle(packet()))
+            .unwrap();
+        assert!(!packets.take_video_dropped());
+        assert_eq!(receiver.try_iter().count(), 2);
+    }
+}
@@ -0,0 +1,371 @@
+use super::*;
+
+use ffmpeg_next::codec::packet::Packet;
+use ffmpeg_next::util::rational::Rational;
+use ffmpeg_next::Rescale;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::worker::WorkerControlMessage;
+
+/// Number of packets a network sink may fall behind before it is disconnected
+const NETWORK_SINK_QUEUE_CAPACITY: usize = 500;
+/// How long the recording waits for network sinks to finish once the local ones are done
+const NETWORK_SINK_FINISH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum StreamKind {
+    Audio,
+    Video,
+    Subtitle,
+}
+
+/// A human readable summary of an encoded stream
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct StreamInfo {
+    pub kind: StreamKind,
+    pub codec: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample_rate: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channels: Option<u32>,
+}
+
+/// An encoded stream as produced by one of the recorders, all packets use `time_base`
+#[derive(Clone)]
+pub(crate) struct StreamDescription {
+    pub kind: StreamKind,
+    pub codec: ffmpeg_next::Codec,
+    pub parameters: ffmpeg_next::codec::Parameters,
+    pub time_base: Rational,
+    pub info: StreamInfo,
+}
+
+/// Something encoded packets can be written to.
+///
+/// A sink is driven by its own worker thread so a slow sink does not hold up the others.
+pub(crate) trait PacketSink: Send {
+    fn name(&self) -> String;
+    fn write_packet(&mut self, kind: StreamKind, packet: Packet) -> Result<(), SlickscreenError>;
+    /// Finalize the sink, `markers` are in ascending order
+    fn finish(&mut self, markers: &[Marker]) -> Result<(), SlickscreenError>;
+}
+
+/// Writes packets into a libavformat muxer
+pub(crate) struct MuxerSink {
+    output: SlickscreenOutput,
+    context: ffmpeg_next::format::context::Output,
+    // (kind, stream index, source time base, stream time base)
+    streams: Vec<(StreamKind, usize, Rational, Rational)>,
+    /// End of the last written packet in microseconds
+    end_us: i64,
+}
+
+impl MuxerSink {
+    /// Adds `streams` and `metadata` to an opened output and writes the container header
+    pub fn new(
+        output: SlickscreenOutput,
+        mut context: ffmpeg_next::format::context::Output,
+        streams: &[StreamDescription],
+        metadata: ffmpeg_next::Dictionary,
+    ) -> Result<Self, SlickscreenError> {
+        context.set_metadata(metadata);
+
+        let mut stream_indices = Vec::with_capacity(streams.len());
+        let mut added_streams = Vec::with_capacity(streams.len());
+        for description in streams {
+            if description.kind == StreamKind::Subtitle
+                && !Self::supports_codec(&context, description.codec)
+            {
+                println!(
+                    "{} cannot store {} subtitles, leaving out the input log",
+                    output.url(),
+                    description.codec.name()
+                );
+                continue;
+            }
+            let mut stream = context
+                .add_stream(description.codec)
+                .map_err(|e| SlickscreenError::OutputError(e.to_string()))?;
+            stream.set_time_base(description.time_base);
+            stream.set_parameters(description.parameters.clone());
+            stream_indices.push(stream.index());
+            added_streams.push(description);
+        }
+
+        context
+            .write_header_with(output.muxer_options())
+            .map_err(|e| SlickscreenError::OutputError(format!("{}: {}", output.url(), e)))?;
+
+        ffmpeg_next::format::context::output::dump(&context, 0, Some(&output.url()));
+
+        // The muxer is free to pick another time base for each stream while writing the header
+        let streams = added_streams
+            .into_iter()
+            .zip(stream_indices)
+            .map(|(description, index)| {
+                let stream_time_base = context
+                    .stream(index)
+                    .expect("it was just added")
+                    .time_base();
+                (
+                    description.kind,
+                    index,
+                    description.time_base,
+                    stream_time_base,
+                )
+            })
+            .collect();
+
+        Ok(Self {
+            output,
+            context,
+            streams,
+            end_us: 0,
+        })
+    }
+
+    /// Only true if the muxer is known to support `codec`
+    fn supports_codec(
+        context: &ffmpeg_next::format::context::Output,
+        codec: ffmpeg_next::Codec,
+    ) -> bool {
+        unsafe {
+            ffmpeg_next::ffi::avformat_query_codec(
+                context.format().as_ptr(),
+                codec.id().into(),
+                ffmpeg_next::ffi::FF_COMPLIANCE_NORMAL as i32,
+            ) == 1
+        }
+    }
+}
+
+impl PacketSink for MuxerSink {
+    fn name(&self) -> String {
+        self.output.url()
+    }
+
+    fn write_packet(&mut self, kind: StreamKind, packet: Packet) -> Result<(), SlickscreenError> {
+        let (_, index, source_time_base, stream_time_base) = match self
+            .streams
+            .iter()
+            .find(|(stream_kind, _, _, _)| *stream_kind == kind)
+        {
+            Some(stream) => *stream,
+            None => return Ok(()),
+        };
+
+        let mut packet = packet;
+        if let Some(pts) = packet.pts() {
+            let end = pts + packet.duration();
+            self.end_us = self
+                .end_us
+                .max(end.rescale(source_time_base, Rational::new(1, 1000000)));
+        }
+        packet.rescale_ts(source_time_base, stream_time_base);
+        packet.set_stream(index);
+        packet
+            .write_interleaved(&mut self.context)
+            .map_err(|e| SlickscreenError::OutputError(e.to_string()))
+    }
+
+    fn finish(&mut self, markers: &[Marker]) -> Result<(), SlickscreenError> {
+        // MP4 and Matroska write their chapters with the trailer, so chapters added
+        // after the header still make it into the file. Other muxers ignore them.
+        let time_base = Rational::new(1, 1000000);
+        for (i, marker) in markers.iter().enumerate() {
+            let start = marker.timestamp_us.min(self.end_us);
+            let end = markers
+                .get(i + 1)
+                .map(|next| next.timestamp_us)
+                .unwrap_or(self.end_us)
+                .clamp(start, self.end_us);
+            if let Err(e) = self
+                .context
+                .add_chapter(i as i64, time_base, start, end, &marker.label)
+            {
+                println!("Unable to add chapter {}: {}", marker.label, e);
+            }
+        }
+
+        self.context
+            .write_trailer()
+            .map_err(|e| SlickscreenError::OutputError(e.to_string()))
+    }
+}
+
+pub(crate) enum SinkMessage {
+    Quit,
+    Packet(StreamKind, Packet),
+}
+
+impl From<worker::WorkerControlMessage> for SinkMessage {
+    fn from(msg: WorkerControlMessage) -> Self {
+        match msg {
+            worker::WorkerControlMessage::Quit => SinkMessage::Quit,
+        }
+    }
+}
+
+struct SinkWorker {
+    name: String,
+    /// A required sink is never dropped, the recording waits for it instead
+    required: bool,
+    failed: Arc<AtomicBool>,
+    worker: worker::Worker<SinkMessage>,
+}
+
+/// Distributes every encoded packet to all sinks.
+///
+/// Local sinks are required and apply backpressure to the recording. Network sinks are
+/// best effort: a network sink that errors or falls too far behind is disconnected and
+/// the remaining sinks keep going.
+pub(crate) struct FanOut {
+    sinks: Vec<SinkWorker>,
+    markers: MarkerList,
+}
+
+impl FanOut {
+    pub fn new(markers: MarkerList) -> Self {
+        Self {
+            sinks: Vec::new(),
+            markers,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    /// The name of every sink along with a flag that is set once it has failed
+    pub fn sink_states(&self) -> Vec<(String, Arc<AtomicBool>)> {
+        self.sinks
+            .iter()
+            .map(|sink| (sink.name.clone(), sink.failed.clone()))
+            .collect()
+    }
+
+    pub fn add_sink(&mut self, mut sink: Box<dyn PacketSink>, required: bool) {
+        let name = sink.name();
+        let failed = Arc::new(AtomicBool::new(false));
+        let worker_failed = failed.clone();
+        let markers = self.markers.clone();
+        let capacity = if required {
+            100
+        } else {
+            NETWORK_SINK_QUEUE_CAPACITY
+        };
+        let worker = worker::Worker::new_consumer_with_capacity(
+            move |control_receiver: crossbeam::channel::Receiver<SinkMessage>| {
+                for msg in control_receiver.iter() {
+                    match msg {
+                        SinkMessage::Quit => {
+                            let mut markers = markers.lock().unwrap().clone();
+                            markers.sort_by_key(|marker| marker.timestamp_us);
+                            if let Err(e) = sink.finish(&markers) {
+                                println!("Error while finalizing {}: {}", sink.name(), e);
+                            }
+                            return;
+                        }
+                        SinkMessage::Packet(kind, packet) => {
+                            if let Err(e) = sink.write_packet(kind, packet) {
+                                println!("Error while writing packet to {}: {}", sink.name(), e);
+                                if !required {
+                                    worker_failed.store(true, Ordering::SeqCst);
+                                    return;
+                                }
+                            }
+                        }
+                    }
+                }
+            },
+            capacity,
+        );
+
+        self.sinks.push(SinkWorker {
+            name,
+            required,
+            failed,
+            worker,
+        });
+    }
+
+    pub fn write_packet(&mut self, kind: StreamKind, packet: Packet) {
+        for sink in self.sinks.iter() {
+            if sink.failed.load(Ordering::SeqCst) {
+                continue;
+            }
+
+            let msg = SinkMessage::Packet(kind, packet.clone());
+            let sender = sink.worker.control_sender();
+            if sink.required {
+                if let Err(_) = sender.send(msg) {
+                    println!("Sink {} has stopped unexpectedly", sink.name);
+                    sink.failed.store(true, Ordering::SeqCst);
+                }
+            } else if let Err(e) = sender.try_send(msg) {
+                if e.is_full() {
+                    println!("Sink {} is falling behind, disconnecting it", sink.name);
+                }
+                sink.failed.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// Finalize every sink. All sinks are told to quit up front so they finish in
+    /// parallel, then the required ones are waited for. Network sinks get until
+    /// `NETWORK_SINK_FINISH_TIMEOUT` to finish and are left behind after that, a dead
+    /// connection must not keep the local recording from being closed.
+    pub fn finish(self) {
+        let (required, network): (Vec<_>, Vec<_>) =
+            self.sinks.into_iter().partition(|sink| sink.required);
+
+        for sink in required.iter() {
+            if let Err(_) = sink.worker.control_sender().send(SinkMessage::Quit) {
+                println!("Sink {} has stopped unexpectedly", sink.name);
+            }
+        }
+        let network: Vec<_> = network
+            .into_iter()
+            .filter(|sink| {
+                // A failed sink's worker may be blocked writing to a dead connection, and a
+                // full queue means it is too far behind to finish in time
+                !sink.failed.load(Ordering::SeqCst)
+                    && sink
+                        .worker
+                        .control_sender()
+                        .try_send(SinkMessage::Quit)
+                        .is_ok()
+            })
+            .collect();
+
+        for sink in required {
+            if let Err(e) = sink.worker.join() {
+                println!("Error while stopping sink {}: {}", sink.name, e);
+            }
+        }
+
+        let deadline = std::time::Instant::now() + NETWORK_SINK_FINISH_TIMEOUT;
+        for sink in network {
+            while !sink.worker.is_finished() && std::time::Instant::now() < deadline {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+            if !sink.worker.is_finished() {
+                println!(
+                    "Sink {} did not finish in time, leaving it behind",
+                    sink.name
+                );
+                continue;
+            }
+            if let Err(e) = sink.worker.join() {
+                println!("Error while stopping sink {}: {}", sink.name, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Counters shared by the recorder threads, read through `SlickscreenStats`
+#[derive(Debug, Default)]
+pub(crate) struct StatsCounters {
+    pub video_frames_captured: AtomicU64,
+    pub video_frames_encoded: AtomicU64,
+    pub video_frames_dropped: AtomicU64,
+    pub video_frames_allocated: AtomicU64,
+    pub audio_frames_captured: AtomicU64,
+    pub audio_frames_dropped: AtomicU64,
+    pub packets_dropped: AtomicU64,
+    pub audio_integrated_loudness: Mutex<Option<f64>>,
+}
+
+impl StatsCounters {
+    #[inline]
+    pub fn add(counter: &AtomicU64, value: u64) {
+        counter.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> SlickscreenStats {
+        SlickscreenStats {
+            video_frames_captured: self.video_frames_captured.load(Ordering::Relaxed),
+            video_frames_encoded: self.video_frames_encoded.load(Ordering::Relaxed),
+            video_frames_dropped: self.video_frames_dropped.load(Ordering::Relaxed),
+            video_frames_allocated: self.video_frames_allocated.load(Ordering::Relaxed),
+            audio_frames_captured: self.audio_frames_captured.load(Ordering::Relaxed),
+            audio_frames_dropped: self.audio_frames_dropped.load(Ordering::Relaxed),
+            packets_dropped: self.packets_dropped.load(Ordering::Relaxed),
+            audio_integrated_loudness: *self.audio_integrated_loudness.lock().unwrap(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct SlickscreenStats {
+    pub video_frames_captured: u64,
+    pub video_frames_encoded: u64,
+    /// Frames that never made it into the recording, because capture could not keep up or
+    /// the encoder queue was full
+    pub video_frames_dropped: u64,
+    /// Frame buffers allocated by the capture pipeline, stays flat once frames are recycled
+    pub video_frames_allocated: u64,
+    pub audio_frames_captured: u64,
+    /// Audio frames the encoder thread had no room for, the audio callback never waits
+    pub audio_frames_dropped: u64,
+    /// Encoded packets dropped because the outputs could not keep up
+    pub packets_dropped: u64,
+    /// EBU R128 integrated loudness of the recorded audio in LUFS, None until enough of it
+    /// was louder than silence
+    pub audio_integrated_loudness: Option<f64>,
+}
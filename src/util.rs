@@ -1,17 +1,75 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
 use std::time::Instant;
 
-#[derive(Copy, Clone, Debug)]
+/// The recording clock, time spent paused is cut out of the timeline.
+///
+/// The pause state is a single atomic so it can be read from the real-time audio
+/// callback without locking. While running it holds the total microseconds spent
+/// paused, while paused it holds `!pts` of the moment the clock was paused, which is
+/// always negative since timestamps never are
+#[derive(Clone, Debug)]
 pub(crate) struct SlickscreenTime {
     reference: Instant,
+    pause_state: Arc<AtomicI64>,
 }
 
 impl SlickscreenTime {
     pub fn new(reference: Instant) -> Self {
-        SlickscreenTime { reference }
+        SlickscreenTime {
+            reference,
+            pause_state: Arc::new(AtomicI64::new(0)),
+        }
     }
 
     #[inline]
-    pub fn pts_now(&self) -> i64 {
+    fn elapsed_micros(&self) -> i64 {
         (self.reference.elapsed().as_micros() & (i64::MAX as u128)) as i64
     }
+
+    /// The current timestamp, or None if the clock is paused
+    #[inline]
+    pub fn running_pts(&self) -> Option<i64> {
+        let pause_state = self.pause_state.load(Ordering::Acquire);
+        if pause_state < 0 {
+            None
+        } else {
+            Some(self.elapsed_micros() - pause_state)
+        }
+    }
+
+    #[inline]
+    pub fn pts_now(&self) -> i64 {
+        let pause_state = self.pause_state.load(Ordering::Acquire);
+        if pause_state < 0 {
+            !pause_state
+        } else {
+            self.elapsed_micros() - pause_state
+        }
+    }
+
+    #[inline]
+    pub fn is_paused(&self) -> bool {
+        self.pause_state.load(Ordering::Acquire) < 0
+    }
+
+    /// Returns false if the clock was already paused
+    pub fn pause(&self) -> bool {
+        let now = self.elapsed_micros();
+        self.pause_state
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |paused_total| {
+                (paused_total >= 0).then(|| !(now - paused_total))
+            })
+            .is_ok()
+    }
+
+    /// Returns false if the clock was not paused
+    pub fn resume(&self) -> bool {
+        let now = self.elapsed_micros();
+        self.pause_state
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |paused| {
+                (paused < 0).then(|| now - !paused)
+            })
+            .is_ok()
+    }
 }
@@ -8,25 +8,312 @@ use ffmpeg_next::codec::Context;
 use ffmpeg_next::util::format::pixel::Pixel as ffmpeg_Pixel;
 use ffmpeg_next::util::frame::Video as VideoFrame;
 
+use crate::camera::CameraOverlay;
+use crate::cursor::CursorOverlay;
+use crate::filter::VideoFilter;
+use crate::input::InputEventSource;
+use crate::input_log::InputLog;
+use crate::overlay::{
+    BgraImage, ClickOverlay, KeystrokeOverlay, MaskOverlay, Overlay, TextOverlay, TextRenderer,
+};
 use crate::worker::WorkerControlMessage;
 
-fn chunked_copy(dst: &mut [u8], dst_stride: usize, src: &[u8], src_stride: usize, width: usize) {
+/// The capture loop aims for 60 frames per second
+const FRAME_INTERVAL: std::time::Duration = std::time::Duration::from_micros(16666);
+/// Screen buffers whose rows start at this alignment are converted in place, others are
+/// copied first so swscale does not fall back to its slow unaligned path
+const SCREEN_BUFFER_ALIGNMENT: usize = 16;
+
+/// Copy `height` rows of `row_length` bytes between two planes whose rows start every
+/// `dst_stride` and `src_stride` bytes. Either plane may have padding at the end of its rows,
+/// and the last row of either may be cut short right after its pixels.
+///
+/// The capture buffer is reused by `scrap` for the next frame while the encoder thread may
+/// still be reading the previous one, so it is copied rather than wrapped unless it is
+/// converted right away, see `convert_screen_buffer`. When both planes have the same stride
+/// this is a single copy of the whole plane.
+fn copy_plane(
+    dst: &mut [u8],
+    dst_stride: usize,
+    src: &[u8],
+    src_stride: usize,
+    row_length: usize,
+    height: usize,
+) {
+    if height == 0 || row_length == 0 {
+        return;
+    }
+    assert!(row_length <= dst_stride, "destination stride is too small");
+    assert!(row_length <= src_stride, "source stride is too small");
+    assert!(dst.len() >= (height - 1) * dst_stride + row_length);
+    assert!(src.len() >= (height - 1) * src_stride + row_length);
+
     if dst_stride == src_stride {
-        dst.copy_from_slice(src);
+        let length = (height - 1) * src_stride + row_length;
+        dst[..length].copy_from_slice(&src[..length]);
     } else {
-        assert!(dst_stride <= width);
-        assert!(src_stride <= width);
-        assert_eq!(dst.len() % dst_stride, 0);
-        assert_eq!(src.len() % src_stride, 0);
         for (dst_row, src_row) in dst
-            .chunks_exact_mut(dst_stride)
-            .zip(src.chunks_exact(src_stride))
+            .chunks_mut(dst_stride)
+            .zip(src.chunks(src_stride))
+            .take(height)
         {
-            (dst_row[..width]).copy_from_slice(&src_row[..width]);
+            dst_row[..row_length].copy_from_slice(&src_row[..row_length]);
+        }
+    }
+}
+
+/// The stride of a plane of `height` rows that takes up `len` bytes. The last row may be
+/// cut short right after its pixels, so it is left out. Row padding is always much smaller
+/// than the height of a screen, so a full last row does not throw this off either
+fn plane_stride(len: usize, row_length: usize, height: usize) -> usize {
+    if height <= 1 {
+        row_length
+    } else {
+        (len - row_length) / (height - 1)
+    }
+}
+
+/// Convert a `width` by `height` BGRA screen buffer whose rows are `stride` bytes apart for
+/// the encoder, reading it where it is instead of copying it into a frame first
+fn convert_screen_buffer(
+    converter: &mut ffmpeg_next::software::scaling::Context,
+    screen_buffer: &[u8],
+    stride: usize,
+    width: usize,
+    height: usize,
+    output: &mut VideoFrame,
+) -> Result<(), ffmpeg_next::Error> {
+    assert!(stride >= width * 4);
+    assert!(height == 0 || screen_buffer.len() >= (height - 1) * stride + width * 4);
+
+    // The frame only points at the screen buffer, it owns no buffers that could be freed
+    let mut input = VideoFrame::empty();
+    unsafe {
+        let frame = input.as_mut_ptr();
+        (*frame).format = ffmpeg_next::ffi::AVPixelFormat::from(ffmpeg_Pixel::BGRA) as i32;
+        (*frame).width = width as i32;
+        (*frame).height = height as i32;
+        (*frame).data[0] = screen_buffer.as_ptr() as *mut u8;
+        (*frame).linesize[0] = stride as i32;
+    }
+    converter.run(&input, output)
+}
+
+/// Create the converter from captured BGRA frames to the encoder's pixel format.
+///
+/// swscale defaults to BT.601 coefficients and limited range, which does not match what the
+/// encoder signals, so the matrix and both ranges are set explicitly for YUV formats.
+fn frame_converter(
+    width: u32,
+    height: u32,
+    chroma_format: ChromaFormat,
+    color_range: ColorRange,
+) -> Result<ffmpeg_next::software::scaling::Context, ffmpeg_next::Error> {
+    use ffmpeg_next::ffi::{sws_getCoefficients, sws_setColorspaceDetails, SWS_CS_ITU709};
+
+    let mut converter = ffmpeg_next::software::scaling::Context::get(
+        ffmpeg_Pixel::BGRA,
+        width,
+        height,
+        chroma_format.pixel_format(),
+        width,
+        height,
+        ffmpeg_next::software::scaling::Flags::BILINEAR
+            | ffmpeg_next::software::scaling::Flags::ACCURATE_RND
+            | ffmpeg_next::software::scaling::Flags::FULL_CHR_H_INT,
+    )?;
+    if chroma_format.is_rgb() {
+        // Only the channel layout changes, there is no matrix or range to apply
+        return Ok(converter);
+    }
+    let result = unsafe {
+        let coefficients = sws_getCoefficients(SWS_CS_ITU709 as i32);
+        sws_setColorspaceDetails(
+            converter.as_mut_ptr(),
+            // The source is RGB so its table is unused, but the range matters
+            coefficients,
+            1,
+            coefficients,
+            color_range.is_full() as i32,
+            // Neutral brightness, contrast and saturation in 16.16 fixed point
+            0,
+            1 << 16,
+            1 << 16,
+        )
+    };
+    if result < 0 {
+        return Err(ffmpeg_next::Error::from(result));
+    }
+    Ok(converter)
+}
+
+/// Recycles frames of one format and size, so capturing at 60 frames per second does not
+/// allocate a full screen buffer for every frame
+#[derive(Clone)]
+struct FramePool {
+    format: ffmpeg_Pixel,
+    width: u32,
+    height: u32,
+    capacity: usize,
+    frames: std::sync::Arc<std::sync::Mutex<Vec<VideoFrame>>>,
+    stats: std::sync::Arc<StatsCounters>,
+}
+
+impl FramePool {
+    fn new(
+        format: ffmpeg_Pixel,
+        width: u32,
+        height: u32,
+        capacity: usize,
+        stats: std::sync::Arc<StatsCounters>,
+    ) -> Self {
+        Self {
+            format,
+            width,
+            height,
+            capacity,
+            frames: std::sync::Arc::new(std::sync::Mutex::new(Vec::with_capacity(capacity))),
+            stats,
+        }
+    }
+
+    /// A recycled frame if one is free, its contents are stale and must be overwritten
+    fn get(&self) -> VideoFrame {
+        let mut frames = self.frames.lock().unwrap();
+        while let Some(mut frame) = frames.pop() {
+            // The encoder may still hold a reference to the buffers of a frame it was sent,
+            // writing to those would corrupt the frames it has yet to encode
+            if unsafe { ffmpeg_next::ffi::av_frame_is_writable(frame.as_mut_ptr()) } != 0 {
+                return frame;
+            }
+        }
+        drop(frames);
+
+        StatsCounters::add(&self.stats.video_frames_allocated, 1);
+        VideoFrame::new(self.format, self.width, self.height)
+    }
+
+    /// Return a frame for reuse, frames beyond the capacity of the pool are freed
+    fn put(&self, frame: VideoFrame) {
+        let mut frames = self.frames.lock().unwrap();
+        if frames.len() < self.capacity {
+            frames.push(frame);
         }
     }
 }
 
+/// Downscales captured frames and encodes them as JPEG for the live preview
+struct PreviewEncoder {
+    encoder: encoder::video::Encoder,
+    width: u32,
+    height: u32,
+    interval: std::time::Duration,
+    last_preview: Option<std::time::Instant>,
+    preview_frame: std::sync::Arc<PreviewFrame>,
+}
+
+impl PreviewEncoder {
+    fn new(
+        config: &PreviewConfig,
+        display_width: usize,
+        display_height: usize,
+        preview_frame: std::sync::Arc<PreviewFrame>,
+    ) -> Result<Self, SlickscreenError> {
+        let width = config.width.min(display_width as u32) & !1;
+        let height = ((display_height as u64 * width as u64 / display_width as u64) as u32) & !1;
+
+        let mut encoder = Video(Encoder(Context::new()));
+        encoder.set_time_base(ffmpeg_next::util::rational::Rational::new(1, 1000000));
+        encoder.set_format(ffmpeg_Pixel::YUVJ420P);
+        encoder.set_width(width);
+        encoder.set_height(height);
+        encoder.set_flags(ffmpeg_next::codec::Flags::QSCALE);
+        // FF_QP2LAMBDA * 5, a decent quality for a small preview
+        encoder.set_quality(118 * 5);
+        let encoder = encoder
+            .open_as(
+                encoder::find(ffmpeg_next::codec::Id::MJPEG)
+                    .ok_or(SlickscreenError::VideoEncoderNotFound("mjpeg".to_string()))?,
+            )
+            .map_err(|e| SlickscreenError::VideoEncoderNotFound(e.to_string()))?;
+
+        Ok(Self {
+            encoder,
+            width,
+            height,
+            interval: config.interval,
+            last_preview: None,
+            preview_frame,
+        })
+    }
+
+    fn is_due(&self, now: std::time::Instant) -> bool {
+        match self.last_preview {
+            Some(last_preview) => now.duration_since(last_preview) >= self.interval,
+            None => true,
+        }
+    }
+
+    fn encode(
+        &mut self,
+        scaler: &mut ffmpeg_next::software::scaling::Context,
+        bgra_frame: &VideoFrame,
+    ) -> Result<(), ffmpeg_next::Error> {
+        self.last_preview = Some(std::time::Instant::now());
+
+        let mut frame = VideoFrame::new(ffmpeg_Pixel::YUVJ420P, self.width, self.height);
+        scaler.run(bgra_frame, &mut frame)?;
+        self.encoder.send_frame(&frame)?;
+
+        let mut packet = ffmpeg_next::Packet::empty();
+        while let Ok(_) = self.encoder.receive_packet(&mut packet) {
+            if let Some(data) = packet.data() {
+                self.preview_frame.publish(data.to_vec());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decides which frames must be keyframes.
+///
+/// Keyframes are placed on a fixed grid of timestamps for every period, so segment
+/// boundaries of outputs such as HLS always start with a keyframe.
+struct KeyframeSchedule {
+    // (period in microseconds, index of the last grid slot that got a keyframe)
+    periods: Vec<(i64, i64)>,
+    requested: bool,
+}
+
+impl KeyframeSchedule {
+    fn new(periods: &[std::time::Duration]) -> Self {
+        Self {
+            periods: periods
+                .iter()
+                .map(|period| (period.as_micros().max(1) as i64, -1))
+                .collect(),
+            requested: false,
+        }
+    }
+
+    fn request(&mut self) {
+        self.requested = true;
+    }
+
+    /// Returns true if the frame at `pts` must be a keyframe
+    fn is_due(&mut self, pts: i64) -> bool {
+        let mut due = std::mem::take(&mut self.requested);
+        for (period, last_slot) in self.periods.iter_mut() {
+            let slot = pts / *period;
+            if slot != *last_slot {
+                *last_slot = slot;
+                due = true;
+            }
+        }
+        due
+    }
+}
+
 pub(super) enum VideoRecorderMessage {
     Quit,
 }
@@ -39,57 +326,424 @@ impl From<worker::WorkerControlMessage> for VideoRecorderMessage {
     }
 }
 
+/// A captured frame on its way to the encoder thread
+struct CapturedFrame {
+    /// BGRA, or already in the encoder's pixel format if `converted`
+    frame: VideoFrame,
+    pts: i64,
+    keyframe: bool,
+    /// Converted straight from the screen buffer on the capture thread, which happens when
+    /// nothing draws on or filters the frame
+    converted: bool,
+}
+
+enum EncoderMessage {
+    Quit,
+    Frame(CapturedFrame),
+}
+
+impl From<worker::WorkerControlMessage> for EncoderMessage {
+    fn from(msg: WorkerControlMessage) -> Self {
+        match msg {
+            worker::WorkerControlMessage::Quit => EncoderMessage::Quit,
+        }
+    }
+}
+
+/// Forward every packet the encoder has ready, returns the number of packets dropped by the
+/// queue or None if the consumer is gone
+fn send_video_packets(
+    encoder: &mut encoder::video::Encoder,
+    worker_sender: &SlickscreenMessageSender,
+) -> Option<u64> {
+    let mut dropped = 0;
+    let mut packet = ffmpeg_next::Packet::empty();
+    while let Ok(_) = encoder.receive_packet(&mut packet) {
+        match worker_sender.send(SlickscreenMessage::Video(packet.clone())) {
+            Ok(count) => dropped += count,
+            Err(e) => {
+                println!(
+                    "Unable to send encoded video packet. Video encoder worker exiting. - {}",
+                    e
+                );
+                return None;
+            }
+        }
+    }
+    Some(dropped)
+}
+
+/// Converts BGRA frames to the encoder's pixel format and encodes them, lives on the
+/// encoder thread
+struct FrameEncoder {
+    encoder: encoder::video::Encoder,
+    converter: ffmpeg_next::software::scaling::Context,
+    yuv_pool: FramePool,
+    worker_sender: SlickscreenMessageSender,
+    stats: std::sync::Arc<StatsCounters>,
+    /// Set when a keyframe is scheduled
+    keyframe_pending: bool,
+}
+
+impl FrameEncoder {
+    /// Encode a frame whose pts is in microseconds, returns false if the encoder failed or
+    /// the consumer is gone
+    fn encode(&mut self, bgra_frame: &VideoFrame) -> bool {
+        let mut frame = self.yuv_pool.get();
+        if let Err(e) = self.converter.run(bgra_frame, &mut frame) {
+            println!("Error while converting frame: {:?}", e);
+            return false;
+        }
+        frame.set_pts(bgra_frame.pts());
+        self.encode_converted(frame)
+    }
+
+    /// Encode a frame that is already in the encoder's pixel format and came from
+    /// `yuv_pool`, it goes back there afterwards
+    fn encode_converted(&mut self, mut frame: VideoFrame) -> bool {
+        // A dropped video packet, no matter which producer evicted it, breaks decoding until
+        // the next keyframe
+        let keyframe =
+            std::mem::take(&mut self.keyframe_pending) | self.worker_sender.take_video_dropped();
+        frame.set_kind(if keyframe {
+            ffmpeg_next::picture::Type::I
+        } else {
+            ffmpeg_next::picture::Type::None
+        });
+
+        let result = self.encoder.send_frame(&frame);
+        self.yuv_pool.put(frame);
+        if let Err(e) = result {
+            println!("Error while encoding video frame: {}", e);
+            return false;
+        }
+        StatsCounters::add(&self.stats.video_frames_encoded, 1);
+        match send_video_packets(&mut self.encoder, &self.worker_sender) {
+            Some(dropped) => StatsCounters::add(&self.stats.packets_dropped, dropped),
+            None => return false,
+        }
+        true
+    }
+
+    /// Drain frames still buffered by the encoder, e.g. for lookahead
+    fn finish(&mut self) {
+        if let Err(e) = self.encoder.send_eof() {
+            println!("Error while flushing video encoder: {}", e);
+            return;
+        }
+        if let Some(dropped) = send_video_packets(&mut self.encoder, &self.worker_sender) {
+            StatsCounters::add(&self.stats.packets_dropped, dropped);
+        }
+    }
+}
+
+/// Captures the screen on one thread and converts and encodes the frames on another, so a
+/// slow encoder does not hold up capturing
 pub(crate) struct VideoRecorder {
+    /// The capture thread, it also receives the control messages
     pub worker: worker::Worker<VideoRecorderMessage>,
+    encoder_worker: worker::Worker<EncoderMessage>,
+    pub stream_description: StreamDescription,
+    /// The subtitle stream of the input log, if one is recorded
+    pub input_log_description: Option<StreamDescription>,
+    pub display_width: usize,
+    pub display_height: usize,
+    /// Set to make the next captured frame a keyframe
+    pub keyframe_requested: std::sync::Arc<std::sync::atomic::AtomicBool>,
 }
 
 impl VideoRecorder {
     pub fn new(
         time_reference: SlickscreenTime,
         slickscreen_message_sender: SlickscreenMessageSender,
+        config: &SlickscreenConfig,
+        preview_frame: std::sync::Arc<PreviewFrame>,
+        stats: std::sync::Arc<StatsCounters>,
+        masks: MaskList,
     ) -> Result<Self, SlickscreenError> {
         let display = scrap::Display::primary()
             .map_err(|e| SlickscreenError::ScreenCaptureError(e.to_string()))?;
         let (display_width, display_height) = (display.width(), display.height());
 
+        let mut preview_encoder = match &config.preview {
+            Some(preview_config) => Some(PreviewEncoder::new(
+                preview_config,
+                display_width,
+                display_height,
+                preview_frame,
+            )?),
+            None => None,
+        };
+
+        if config.keyframe_interval.is_zero() {
+            return Err(SlickscreenError::InvalidEncoderConfig(
+                "keyframe interval must be greater than zero".to_string(),
+            ));
+        }
+
+        let mut video_filter = match &config.video_filter {
+            Some(spec) => Some(VideoFilter::new(
+                spec,
+                display_width as u32,
+                display_height as u32,
+                config.chroma_format.pixel_format(),
+            )?),
+            None => None,
+        };
+        // The filter may scale the frames, the encoder gets whatever size comes out of it
+        let (video_width, video_height) = match &video_filter {
+            Some(video_filter) => (video_filter.width(), video_filter.height()),
+            None => (display_width as u32, display_height as u32),
+        };
+
         let encoder_context = Context::new();
         let mut encoder = Video(Encoder(encoder_context));
         // https://github.com/mirror/x264/blob/master/encoder/encoder.c
         // search for: /* Detect default ffmpeg settings and terminate with an error. */
         encoder.set_time_base(ffmpeg_next::util::rational::Rational::new(1, 1000000));
-        encoder.set_format(ffmpeg_Pixel::YUV420P);
-        encoder.set_width(display_width as u32);
-        encoder.set_height(display_height as u32);
-        encoder.set_gop(4096);
+        let chroma_format = config.chroma_format;
+        let encoder_name = chroma_format.encoder_name();
+        encoder.set_format(chroma_format.pixel_format());
+        encoder.set_width(video_width);
+        encoder.set_height(video_height);
+        // Keyframes are forced on schedule by pts, which resets the gop count of x264, so the
+        // interval holds whatever the capture rate. The capture loop is not rate limited, so
+        // the gop, counted in frames, is only a backstop sized for FRAME_INTERVAL: faster
+        // captures may get an extra keyframe from it, slower ones always reach the schedule
+        // first
+        encoder.set_gop(
+            (config.keyframe_interval.as_secs_f64() / FRAME_INTERVAL.as_secs_f64()).ceil() as u32
+                + 1,
+        );
         encoder.set_max_b_frames(0);
-        encoder.set_colorspace(ffmpeg_next::util::color::Space::BT709);
-        encoder.set_color_range(ffmpeg_next::util::color::Range::JPEG);
+        if chroma_format.is_rgb() {
+            encoder.set_colorspace(ffmpeg_next::util::color::Space::RGB);
+            encoder.set_color_range(ffmpeg_next::util::color::Range::JPEG);
+        } else {
+            encoder.set_colorspace(ffmpeg_next::util::color::Space::BT709);
+            encoder.set_color_range(config.color_range.to_ffmpeg());
+        }
         encoder.set_me_range(16);
-        encoder.set_qmin(10);
-        encoder.set_qmax(51);
+        encoder.set_threading(ffmpeg_next::codec::threading::Config {
+            kind: match config.encoder_threading {
+                EncoderThreading::Frame => ffmpeg_next::codec::threading::Type::Frame,
+                EncoderThreading::Slice => ffmpeg_next::codec::threading::Type::Slice,
+            },
+            count: config.encoder_threads,
+            ..Default::default()
+        });
+        let codec = encoder::find_by_name(encoder_name).ok_or(
+            SlickscreenError::VideoEncoderNotFound(format!("{} not found", encoder_name)),
+        )?;
         let mut encoder_options = ffmpeg_next::Dictionary::new();
-        encoder_options.set("preset", "medium");
-        encoder_options.set("tune", "zerolatency");
-        encoder_options.set("level", "4.2");
-        encoder_options.set("profile", "high");
-        encoder_options.set("refs", "1");
-        encoder_options.set("crf", "15");
-        encoder_options.set("qdiff", "4");
-        encoder_options.set("qcompress", "0.6");
+        config
+            .profile
+            .apply(encoder_name, chroma_format, &mut encoder_options);
+        match config.rate_control {
+            Some(rate_control) => rate_control.apply(encoder_name, &mut encoder_options)?,
+            // FFV1 is always lossless, there is no rate to control
+            None if chroma_format == ChromaFormat::Gbrp => {}
+            None => config
+                .profile
+                .default_rate_control()
+                .apply(encoder_name, &mut encoder_options)?,
+        }
         encoder_options.set("color_primaries", "bt709");
         encoder_options.set("color_trc", "bt709");
-        let mut encoder = encoder
-            .open_as_with(
-                encoder::find_by_name("libx264").ok_or(SlickscreenError::VideoEncoderNotFound(
-                    "not found".to_string(),
-                ))?,
-                encoder_options,
-            )
+        if encoder_name.starts_with("libx264") {
+            // Forced keyframes should be proper IDR frames so decoding can start there
+            encoder_options.set("forced-idr", "1");
+        }
+        // Options passed through from the user override everything above
+        validate_encoder_options(&codec, &config.encoder_options)?;
+        for (key, value) in config.encoder_options.iter() {
+            encoder_options.set(key, value);
+        }
+        let encoder = encoder
+            .open_as_with(codec, encoder_options)
             .map_err(|e| SlickscreenError::VideoEncoderNotFound(e.to_string()))?;
+        let stream_description = StreamDescription {
+            kind: StreamKind::Video,
+            codec,
+            parameters: ffmpeg_next::codec::Parameters::from(&encoder),
+            time_base: ffmpeg_next::util::rational::Rational::new(1, 1000000),
+            info: StreamInfo {
+                kind: StreamKind::Video,
+                codec: codec.name().to_string(),
+                width: Some(video_width),
+                height: Some(video_height),
+                sample_rate: None,
+                channels: None,
+            },
+        };
+
+        let mut keyframe_periods = vec![config.keyframe_interval];
+        for output in config.outputs.iter() {
+            if let SlickscreenOutput::Hls {
+                segment_duration, ..
+            } = output
+            {
+                keyframe_periods.push(*segment_duration);
+            }
+        }
+        let mut keyframe_schedule = KeyframeSchedule::new(&keyframe_periods);
+
+        // Drawn in order onto every captured frame
+        let mut overlays: Vec<Box<dyn Overlay>> = vec![Box::new(MaskOverlay::new(masks))];
+        if let Some(camera_config) = &config.camera {
+            overlays.push(Box::new(CameraOverlay::new(camera_config)?));
+        }
+        let input_source: Option<Box<dyn InputEventSource>> =
+            if config.input_overlay.is_some() || config.input_log.is_some() {
+                Some(config.input_source.open()?)
+            } else {
+                None
+            };
+        let (mut input_log, input_log_description) = match config.input_log {
+            Some(format) => {
+                let (input_log, description) =
+                    InputLog::new(format, slickscreen_message_sender.clone(), stats.clone())?;
+                (Some(input_log), Some(description))
+            }
+            None => (None, None),
+        };
+        if config
+            .input_overlay
+            .as_ref()
+            .map_or(false, |input_config| input_config.clicks)
+        {
+            overlays.push(Box::new(ClickOverlay::new()));
+        }
+        match CursorOverlay::new(config.cursor) {
+            Ok(Some(cursor_overlay)) => overlays.push(Box::new(cursor_overlay)),
+            Ok(None) => {}
+            Err(e) => println!("Recording without the mouse cursor - {}", e),
+        }
+        if let Some(input_config) = config
+            .input_overlay
+            .as_ref()
+            .filter(|input_config| input_config.keystrokes)
+        {
+            let font_path = input_config.font_path.as_ref().ok_or_else(|| {
+                SlickscreenError::OverlayError("keystrokes need a font".to_string())
+            })?;
+            let text = TextRenderer::load(font_path, input_config.font_size)?;
+            overlays.push(Box::new(KeystrokeOverlay::new(text)));
+        }
+        if let Some(text_config) = &config.text_overlay {
+            overlays.push(Box::new(TextOverlay::new(text_config)?));
+        }
+        let color_range = config.color_range;
+
+        // Each queued frame holds a full BGRA screen, so the queue is kept short
+        let (frame_sender, frame_receiver) =
+            crossbeam::channel::bounded(config.frame_queue_capacity.max(1));
+        let encoder_stats = stats.clone();
+        // Frames are in flight in the queue, in the capture thread and in the encoder thread
+        let bgra_pool = FramePool::new(
+            ffmpeg_Pixel::BGRA,
+            display_width as u32,
+            display_height as u32,
+            config.frame_queue_capacity.max(1) + 2,
+            stats.clone(),
+        );
+        let encoder_bgra_pool = bgra_pool.clone();
+        // The capture thread converts frames itself when nothing draws on or filters them,
+        // which skips copying the screen into a BGRA frame
+        let zero_copy = video_filter.is_none() && preview_encoder.is_none();
+        // Frame threading keeps a reference to a few frames until they are encoded. Frames
+        // converted by the capture thread are also in flight like the BGRA frames
+        let yuv_pool = FramePool::new(
+            chroma_format.pixel_format(),
+            video_width,
+            video_height,
+            if zero_copy {
+                config.frame_queue_capacity.max(1) + 6
+            } else {
+                4
+            },
+            stats.clone(),
+        );
+        let capture_yuv_pool = yuv_pool.clone();
+        let encoder_worker = worker::Worker::new_consumer_with_channel(
+            frame_sender.clone(),
+            frame_receiver.clone(),
+            move |control_receiver: crossbeam::channel::Receiver<EncoderMessage>| {
+                let converter =
+                    frame_converter(video_width, video_height, chroma_format, color_range)
+                        .expect("failed to create frame converter");
+                let mut frame_encoder = FrameEncoder {
+                    encoder,
+                    converter,
+                    yuv_pool,
+                    worker_sender: slickscreen_message_sender,
+                    stats: encoder_stats,
+                    keyframe_pending: false,
+                };
 
+                for msg in control_receiver.iter() {
+                    match msg {
+                        EncoderMessage::Quit => {
+                            if let Some(video_filter) = video_filter.as_mut() {
+                                // Frames held back by the filter, e.g. by a frame rate change
+                                if let Err(e) = video_filter.flush() {
+                                    println!("Error while flushing video filter: {}", e);
+                                }
+                                while let Some(filtered_frame) = video_filter.pull() {
+                                    if !frame_encoder.encode(&filtered_frame) {
+                                        return;
+                                    }
+                                }
+                            }
+                            frame_encoder.finish();
+                            return;
+                        }
+                        EncoderMessage::Frame(captured_frame) => {
+                            let mut bgra_frame = captured_frame.frame;
+                            bgra_frame.set_pts(Some(captured_frame.pts));
+                            frame_encoder.keyframe_pending |= captured_frame.keyframe;
+                            if captured_frame.converted {
+                                // Never the case with a filter
+                                if !frame_encoder.encode_converted(bgra_frame) {
+                                    return;
+                                }
+                                continue;
+                            }
+
+                            match video_filter.as_mut() {
+                                Some(video_filter) => {
+                                    if let Err(e) = video_filter.push(&bgra_frame) {
+                                        println!("Error while filtering video frame: {}", e);
+                                        return;
+                                    }
+                                    while let Some(filtered_frame) = video_filter.pull() {
+                                        if !frame_encoder.encode(&filtered_frame) {
+                                            return;
+                                        }
+                                    }
+                                    // The graph holds a reference to the frame until its
+                                    // output is pulled, the pool would not reuse it earlier
+                                    encoder_bgra_pool.put(bgra_frame);
+                                }
+                                None => {
+                                    let encoded = frame_encoder.encode(&bgra_frame);
+                                    encoder_bgra_pool.put(bgra_frame);
+                                    if !encoded {
+                                        return;
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            },
+        );
+
+        let keyframe_requested = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let capture_keyframe_requested = keyframe_requested.clone();
         let worker = worker::Worker::new(
-            slickscreen_message_sender,
-            move |worker_sender: SlickscreenMessageSender,
+            PolicySender::new(frame_sender, frame_receiver, config.frame_drop_policy),
+            move |frame_sender: PolicySender<EncoderMessage>,
                   control_receiver: crossbeam::channel::Receiver<VideoRecorderMessage>| {
                 use std::io::ErrorKind::WouldBlock;
 
@@ -98,68 +752,187 @@ impl VideoRecorder {
                 let mut capturer =
                     scrap::Capturer::new(display).expect("failed to initialize screen capturer");
 
-                let frame = VideoFrame::new(
-                    ffmpeg_Pixel::BGRA,
-                    display_width as u32,
-                    display_height as u32,
-                );
-                let mut converter = frame
-                    .converter(ffmpeg_Pixel::YUV420P)
-                    .expect("failed to create bgra -> yuv420 converter");
-                drop(frame);
+                let mut preview_scaler = preview_encoder.as_ref().map(|preview_encoder| {
+                    ffmpeg_next::software::scaling::Context::get(
+                        ffmpeg_Pixel::BGRA,
+                        display_width as u32,
+                        display_height as u32,
+                        ffmpeg_Pixel::YUVJ420P,
+                        preview_encoder.width,
+                        preview_encoder.height,
+                        ffmpeg_next::software::scaling::Flags::BILINEAR,
+                    )
+                    .expect("failed to create preview scaler")
+                });
+
+                let mut capture_converter = zero_copy.then(|| {
+                    frame_converter(
+                        display_width as u32,
+                        display_height as u32,
+                        chroma_format,
+                        color_range,
+                    )
+                    .expect("failed to create frame converter")
+                });
 
+                let mut input_events = Vec::new();
+                // Set when a frame due to be a keyframe was dropped before it was queued
+                let mut carried_keyframe = false;
                 loop {
                     let start_of_frame = std::time::Instant::now();
-                    let expected_next_frame =
-                        start_of_frame.add(std::time::Duration::from_micros(16666));
+                    let expected_next_frame = start_of_frame.add(FRAME_INTERVAL);
+
+                    if let Some(input_source) = input_source.as_mut() {
+                        input_events.clear();
+                        input_source.poll(&mut input_events);
+                        // Input while paused is not part of the recording
+                        if let Some(now) = time_reference.running_pts() {
+                            for event in input_events.iter() {
+                                for overlay in overlays.iter_mut() {
+                                    overlay.input_event(event, now);
+                                }
+                                if let Some(input_log) = input_log.as_mut() {
+                                    input_log.input_event(event, now);
+                                }
+                            }
+                            if let Some(input_log) = input_log.as_mut() {
+                                input_log.expire(now);
+                            }
+                        }
+                    }
 
-                    match capturer.frame() {
+                    let capture_result = if time_reference.is_paused() {
+                        // Nothing is captured while paused, only wait for control messages
+                        std::thread::sleep(std::time::Duration::from_millis(10));
+                        Err(std::io::Error::from(WouldBlock))
+                    } else {
+                        capturer.frame()
+                    };
+
+                    match capture_result {
                         Ok(screen_buffer) => {
                             let now = time_reference.pts_now();
 
-                            let screen_buffer_stride = screen_buffer.len() / display_height;
                             let pixel_size = 4;
                             let row_length = pixel_size * display_width;
+                            let screen_buffer_stride =
+                                plane_stride(screen_buffer.len(), row_length, display_height);
 
-                            let mut bgra_frame = VideoFrame::new(
-                                ffmpeg_Pixel::BGRA,
-                                display_width as u32,
-                                display_height as u32,
-                            );
-                            let bgra_frame_stride = bgra_frame.stride(0);
-                            chunked_copy(
-                                bgra_frame.data_mut(0),
-                                bgra_frame_stride,
-                                &screen_buffer,
-                                screen_buffer_stride,
-                                row_length,
-                            );
+                            // Without anything to draw the screen buffer is converted
+                            // where it is, otherwise it is copied into a frame to draw on
+                            let zero_copy_converter = capture_converter.as_mut().filter(|_| {
+                                overlays.iter().all(|overlay| !overlay.draws_at(now))
+                                    && screen_buffer_stride % SCREEN_BUFFER_ALIGNMENT == 0
+                                    && (screen_buffer.as_ptr() as usize)
+                                        % SCREEN_BUFFER_ALIGNMENT
+                                        == 0
+                            });
+                            let (frame, converted) = match zero_copy_converter {
+                                Some(converter) => {
+                                    let mut frame = capture_yuv_pool.get();
+                                    if let Err(e) = convert_screen_buffer(
+                                        converter,
+                                        &screen_buffer,
+                                        screen_buffer_stride,
+                                        display_width,
+                                        display_height,
+                                        &mut frame,
+                                    ) {
+                                        println!("Error while converting frame: {:?}", e);
+                                        return;
+                                    }
+                                    (frame, true)
+                                }
+                                None => {
+                                    let mut bgra_frame = bgra_pool.get();
+                                    let bgra_frame_stride = bgra_frame.stride(0);
+                                    copy_plane(
+                                        bgra_frame.data_mut(0),
+                                        bgra_frame_stride,
+                                        &screen_buffer,
+                                        screen_buffer_stride,
+                                        row_length,
+                                        display_height,
+                                    );
 
-                            let mut frame = VideoFrame::new(
-                                ffmpeg_Pixel::YUV420P,
-                                display_width as u32,
-                                display_height as u32,
-                            );
-                            if let Err(e) = converter.run(&bgra_frame, &mut frame) {
-                                println!("Error while converting frame to yuv: {:?}", e);
-                                return;
-                            }
-                            frame.set_pts(Some(now));
+                                    let mut image = BgraImage::new(
+                                        bgra_frame.data_mut(0),
+                                        bgra_frame_stride,
+                                        display_width,
+                                        display_height,
+                                    );
+                                    for overlay in overlays.iter_mut() {
+                                        overlay.draw(&mut image, now);
+                                    }
 
-                            if let Err(e) = encoder.send_frame(&frame) {
-                                println!("Error while encoding video frame: {}", e);
-                                return;
-                            }
+                                    if let (Some(preview_encoder), Some(preview_scaler)) =
+                                        (preview_encoder.as_mut(), preview_scaler.as_mut())
+                                    {
+                                        if preview_encoder.is_due(start_of_frame) {
+                                            if let Err(e) =
+                                                preview_encoder.encode(preview_scaler, &bgra_frame)
+                                            {
+                                                println!(
+                                                    "Error while encoding preview frame: {}",
+                                                    e
+                                                );
+                                            }
+                                        }
+                                    }
+                                    (bgra_frame, false)
+                                }
+                            };
 
-                            let mut packet = ffmpeg_next::Packet::empty();
-                            while let Ok(_) = encoder.receive_packet(&mut packet) {
-                                if let Err(e) =
-                                    worker_sender.send(SlickscreenMessage::Video(packet.clone()))
-                                {
-                                    println!("Unable to send encoded video packet. Video encoder worker exiting. - {}", e);
+                            if capture_keyframe_requested
+                                .swap(false, std::sync::atomic::Ordering::Relaxed)
+                            {
+                                keyframe_schedule.request();
+                            }
+                            let captured_frame = CapturedFrame {
+                                frame,
+                                pts: now,
+                                // The schedule must see every frame, so no short circuit
+                                keyframe: keyframe_schedule.is_due(now)
+                                    | std::mem::take(&mut carried_keyframe),
+                                converted,
+                            };
+                            // Frames evicted from the queue go back to the pool instead of
+                            // being freed. A scheduled keyframe moves on to the next frame
+                            // that is queued, or to the next frame captured
+                            let queue_dropped = match frame_sender.send_with(
+                                EncoderMessage::Frame(captured_frame),
+                                |dropped, queued| {
+                                    if let EncoderMessage::Frame(dropped) = dropped {
+                                        match queued {
+                                            Some(EncoderMessage::Frame(queued)) => {
+                                                queued.keyframe |= dropped.keyframe
+                                            }
+                                            _ => carried_keyframe |= dropped.keyframe,
+                                        }
+                                        if dropped.converted {
+                                            capture_yuv_pool.put(dropped.frame);
+                                        } else {
+                                            bgra_pool.put(dropped.frame);
+                                        }
+                                    }
+                                },
+                            ) {
+                                Ok(dropped) => dropped,
+                                Err(e) => {
+                                    println!("Unable to send captured frame. Video capture worker exiting. - {}", e);
                                     return;
                                 }
-                            }
+                            };
+
+                            // Every full frame interval spent on this frame is a frame that
+                            // could not be captured
+                            let missed_frames =
+                                start_of_frame.elapsed().as_micros() / FRAME_INTERVAL.as_micros();
+                            StatsCounters::add(&stats.video_frames_captured, 1);
+                            StatsCounters::add(
+                                &stats.video_frames_dropped,
+                                missed_frames as u64 + queue_dropped,
+                            );
                         }
                         Err(ref e) if e.kind() == WouldBlock => {}
                         Err(_) => {
@@ -171,6 +944,9 @@ impl VideoRecorder {
                     loop {
                         match control_receiver.try_recv() {
                             Ok(VideoRecorderMessage::Quit) => {
+                                if let Some(input_log) = input_log.as_mut() {
+                                    input_log.flush(time_reference.pts_now());
+                                }
                                 return;
                             }
                             Err(crossbeam::channel::TryRecvError::Disconnected) => {
@@ -192,6 +968,400 @@ impl VideoRecorder {
             },
         );
 
-        Ok(Self { worker })
+        Ok(Self {
+            worker,
+            encoder_worker,
+            stream_description,
+            input_log_description,
+            display_width,
+            display_height,
+            keyframe_requested,
+        })
+    }
+
+    /// Stop capturing, then let the encoder finish the frames that were already captured
+    pub fn stop(self) -> Result<(), worker::WorkerError> {
+        let capture_result = self.worker.stop();
+        self.encoder_worker.stop()?;
+        capture_result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A small linear congruential generator, enough to vary the test cases
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self, bound: usize) -> usize {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (self.0 >> 33) as usize % bound
+        }
+    }
+
+    /// A plane with `height` rows of `row_length` bytes `stride` bytes apart, optionally
+    /// with the last row cut short after its pixels. Pixel bytes are numbered, padding is 0xee
+    fn plane(stride: usize, row_length: usize, height: usize, short_last_row: bool) -> Vec<u8> {
+        let mut plane = vec![0xee; stride * height];
+        for row in 0..height {
+            for x in 0..row_length {
+                plane[row * stride + x] = (row * 31 + x * 7) as u8;
+            }
+        }
+        if short_last_row && height > 0 {
+            plane.truncate((height - 1) * stride + row_length);
+        }
+        plane
+    }
+
+    #[test]
+    fn copy_plane_copies_pixels_and_keeps_padding() {
+        let mut rng = Lcg(1);
+        for _ in 0..2000 {
+            let row_length = rng.next(64);
+            let height = rng.next(12);
+            let src_stride = row_length + if rng.next(2) == 0 { 0 } else { rng.next(16) };
+            let dst_stride = row_length + if rng.next(2) == 0 { 0 } else { rng.next(16) };
+            let src = plane(src_stride, row_length, height, rng.next(2) == 0);
+            let short_dst = rng.next(2) == 0;
+            let mut dst = vec![0x55; dst_stride * height];
+            if short_dst && height > 0 {
+                dst.truncate((height - 1) * dst_stride + row_length);
+            }
+
+            copy_plane(&mut dst, dst_stride, &src, src_stride, row_length, height);
+
+            for row in 0..height {
+                let dst_row = &dst[row * dst_stride..];
+                let src_row = &src[row * src_stride..];
+                assert_eq!(
+                    dst_row[..row_length],
+                    src_row[..row_length],
+                    "row {} of {}, row length {}, strides {} -> {}",
+                    row,
+                    height,
+                    row_length,
+                    src_stride,
+                    dst_stride
+                );
+                // Padding of the destination is only overwritten by a single plane copy
+                if src_stride != dst_stride {
+                    let padding_end = dst_row.len().min(dst_stride);
+                    assert!(dst_row[row_length..padding_end].iter().all(|b| *b == 0x55));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn plane_stride_allows_a_short_last_row() {
+        let mut rng = Lcg(2);
+        for _ in 0..2000 {
+            let row_length = 4 * (1 + rng.next(2000));
+            let height = 1 + rng.next(1200);
+            // Padding is smaller than the height, as it is for any real screen
+            let stride = row_length + 4 * rng.next(16).min(height.saturating_sub(2) / 4);
+            let full = stride * height;
+            let short = full - (stride - row_length);
+            let expected = if height == 1 { row_length } else { stride };
+            assert_eq!(plane_stride(full, row_length, height), expected);
+            assert_eq!(plane_stride(short, row_length, height), expected);
+        }
+    }
+
+    /// Encode solid colors losslessly with libx264 and decode them back to RGB, so only the
+    /// conversions between RGB and YUV can change them. Returns the center pixel of every
+    /// decoded frame
+    fn encode_and_decode(color_range: ColorRange, colors: &[[u8; 3]]) -> Vec<[u8; 3]> {
+        use ffmpeg_next::ffi::{sws_getCoefficients, sws_setColorspaceDetails, SWS_CS_ITU709};
+        use ffmpeg_next::software::scaling;
+
+        ffmpeg_next::init().unwrap();
+        let (width, height) = (64, 64);
+        let chroma_format = ChromaFormat::Yuv420;
+
+        let mut encoder = Video(Encoder(Context::new()));
+        encoder.set_time_base(ffmpeg_next::util::rational::Rational::new(1, 25));
+        encoder.set_format(chroma_format.pixel_format());
+        encoder.set_width(width);
+        encoder.set_height(height);
+        encoder.set_max_b_frames(0);
+        encoder.set_colorspace(ffmpeg_next::util::color::Space::BT709);
+        encoder.set_color_range(color_range.to_ffmpeg());
+        let codec = encoder::find_by_name(chroma_format.encoder_name()).unwrap();
+        let mut encoder_options = ffmpeg_next::Dictionary::new();
+        encoder_options.set("preset", "ultrafast");
+        encoder_options.set("qp", "0");
+        let mut encoder = encoder.open_as_with(codec, encoder_options).unwrap();
+
+        let mut converter = frame_converter(width, height, chroma_format, color_range).unwrap();
+        let mut bgra_frame = VideoFrame::new(ffmpeg_Pixel::BGRA, width, height);
+        let mut packets = Vec::new();
+        let mut packet = ffmpeg_next::Packet::empty();
+        for (i, [r, g, b]) in colors.iter().enumerate() {
+            for pixel in bgra_frame.data_mut(0).chunks_mut(4) {
+                pixel.copy_from_slice(&[*b, *g, *r, 255]);
+            }
+            // The encoder may keep a reference to the frames it was sent
+            let mut yuv_frame = VideoFrame::new(chroma_format.pixel_format(), width, height);
+            converter.run(&bgra_frame, &mut yuv_frame).unwrap();
+            yuv_frame.set_pts(Some(i as i64));
+            encoder.send_frame(&yuv_frame).unwrap();
+            while encoder.receive_packet(&mut packet).is_ok() {
+                packets.push(packet.clone());
+            }
+        }
+        encoder.send_eof().unwrap();
+        while encoder.receive_packet(&mut packet).is_ok() {
+            packets.push(packet.clone());
+        }
+
+        let mut decoder = Context::from_parameters(ffmpeg_next::codec::Parameters::from(&encoder))
+            .and_then(|context| context.decoder().video())
+            .unwrap();
+        let center_pixel = |frame: &VideoFrame| {
+            // The range must be signalled in the stream for players to get the colors right
+            assert_eq!(frame.color_range(), color_range.to_ffmpeg());
+            let mut scaler = scaling::Context::get(
+                frame.format(),
+                width,
+                height,
+                ffmpeg_Pixel::RGB24,
+                width,
+                height,
+                scaling::Flags::BILINEAR
+                    | scaling::Flags::ACCURATE_RND
+                    | scaling::Flags::FULL_CHR_H_INT,
+            )
+            .unwrap();
+            unsafe {
+                let coefficients = sws_getCoefficients(SWS_CS_ITU709 as i32);
+                sws_setColorspaceDetails(
+                    scaler.as_mut_ptr(),
+                    coefficients,
+                    color_range.is_full() as i32,
+                    coefficients,
+                    1,
+                    0,
+                    1 << 16,
+                    1 << 16,
+                );
+            }
+            let mut rgb_frame = VideoFrame::empty();
+            scaler.run(frame, &mut rgb_frame).unwrap();
+            let center = (height / 2) as usize * rgb_frame.stride(0) + (width / 2) as usize * 3;
+            let data = rgb_frame.data(0);
+            [data[center], data[center + 1], data[center + 2]]
+        };
+
+        let mut decoded = Vec::new();
+        let mut frame = VideoFrame::empty();
+        for packet in packets.iter() {
+            decoder.send_packet(packet).unwrap();
+            while decoder.receive_frame(&mut frame).is_ok() {
+                decoded.push(center_pixel(&frame));
+            }
+        }
+        decoder.send_eof().unwrap();
+        while decoder.receive_frame(&mut frame).is_ok() {
+            decoded.push(center_pixel(&frame));
+        }
+        decoded
+    }
+
+    fn assert_colors_survive(color_range: ColorRange) {
+        let colors = [
+            [0, 0, 0],
+            [255, 255, 255],
+            [128, 128, 128],
+            [255, 0, 0],
+            [0, 255, 0],
+            [0, 0, 255],
+            [240, 130, 40],
+            [30, 60, 90],
+        ];
+        let decoded = encode_and_decode(color_range, &colors);
+        assert_eq!(decoded.len(), colors.len());
+        for (color, decoded) in colors.iter().zip(decoded.iter()) {
+            let close = color
+                .iter()
+                .zip(decoded.iter())
+                .all(|(a, b)| (*a as i32 - *b as i32).abs() <= 3);
+            assert!(
+                close,
+                "{:?} came back as {:?} in {:?} range",
+                color, decoded, color_range
+            );
+        }
+    }
+
+    #[test]
+    fn limited_range_colors_survive_encoding() {
+        assert_colors_survive(ColorRange::Limited);
+    }
+
+    #[test]
+    fn full_range_colors_survive_encoding() {
+        assert_colors_survive(ColorRange::Full);
+    }
+
+    #[test]
+    fn converting_the_screen_buffer_in_place_matches_copying_it() {
+        ffmpeg_next::init().unwrap();
+        let (width, height) = (64, 48);
+        let row_length = width * 4;
+        let stride = row_length + SCREEN_BUFFER_ALIGNMENT * 3;
+        let screen_buffer = plane(stride, row_length, height, true);
+
+        for chroma_format in [ChromaFormat::Yuv420, ChromaFormat::Yuv444] {
+            let mut converter = frame_converter(
+                width as u32,
+                height as u32,
+                chroma_format,
+                ColorRange::Limited,
+            )
+            .unwrap();
+            let new_frame =
+                || VideoFrame::new(chroma_format.pixel_format(), width as u32, height as u32);
+
+            let mut in_place = new_frame();
+            convert_screen_buffer(
+                &mut converter,
+                &screen_buffer,
+                stride,
+                width,
+                height,
+                &mut in_place,
+            )
+            .unwrap();
+
+            let mut bgra_frame = VideoFrame::new(ffmpeg_Pixel::BGRA, width as u32, height as u32);
+            let bgra_stride = bgra_frame.stride(0);
+            copy_plane(
+                bgra_frame.data_mut(0),
+                bgra_stride,
+                &screen_buffer,
+                stride,
+                row_length,
+                height,
+            );
+            let mut copied = new_frame();
+            converter.run(&bgra_frame, &mut copied).unwrap();
+
+            for index in 0..copied.planes() {
+                let rows = |frame: &VideoFrame| -> Vec<Vec<u8>> {
+                    frame
+                        .data(index)
+                        .chunks(frame.stride(index))
+                        .take(frame.plane_height(index) as usize)
+                        .map(|row| row[..frame.plane_width(index) as usize].to_vec())
+                        .collect()
+                };
+                assert_eq!(rows(&in_place), rows(&copied), "{:?}", chroma_format);
+            }
+        }
+    }
+
+    #[test]
+    fn frame_pool_recycles_frames() {
+        ffmpeg_next::init().unwrap();
+        let stats = std::sync::Arc::new(StatsCounters::default());
+        let pool = FramePool::new(ffmpeg_Pixel::BGRA, 64, 48, 4, stats.clone());
+
+        // Warm up the pool with as many frames as are in flight at once
+        let frames: Vec<_> = (0..3).map(|_| pool.get()).collect();
+        frames.into_iter().for_each(|frame| pool.put(frame));
+        let allocated = stats
+            .video_frames_allocated
+            .load(std::sync::atomic::Ordering::Relaxed);
+        assert_eq!(allocated, 3);
+
+        for _ in 0..1000 {
+            let frames: Vec<_> = (0..3).map(|_| pool.get()).collect();
+            frames.into_iter().for_each(|frame| pool.put(frame));
+        }
+        assert_eq!(
+            stats
+                .video_frames_allocated
+                .load(std::sync::atomic::Ordering::Relaxed),
+            allocated
+        );
+    }
+
+    /// Frames allocated while `captures` screenshots go from a capture thread through a
+    /// dropping queue to a slower encoder thread, like in `VideoRecorder`
+    fn capture_pipeline_allocations(
+        captures: usize,
+        queue_capacity: usize,
+        pool_capacity: usize,
+    ) -> u64 {
+        const WIDTH: usize = 64;
+        const HEIGHT: usize = 48;
+        let stats = std::sync::Arc::new(StatsCounters::default());
+        let pool = FramePool::new(
+            ffmpeg_Pixel::BGRA,
+            WIDTH as u32,
+            HEIGHT as u32,
+            pool_capacity,
+            stats.clone(),
+        );
+        let (sender, receiver) = crossbeam::channel::bounded(queue_capacity);
+        let frame_sender = PolicySender::new(sender, receiver.clone(), DropPolicy::DropOldest);
+
+        let encoder_pool = pool.clone();
+        let encoder = std::thread::spawn(move || {
+            for frame in receiver.iter() {
+                std::thread::sleep(std::time::Duration::from_micros(200));
+                encoder_pool.put(frame);
+            }
+        });
+
+        let screen = plane(WIDTH * 4, WIDTH * 4, HEIGHT, false);
+        for _ in 0..captures {
+            let mut frame = pool.get();
+            let stride = frame.stride(0);
+            copy_plane(
+                frame.data_mut(0),
+                stride,
+                &screen,
+                WIDTH * 4,
+                WIDTH * 4,
+                HEIGHT,
+            );
+            frame_sender
+                .send_with(frame, |dropped, _| pool.put(dropped))
+                .unwrap();
+        }
+        drop(frame_sender);
+        encoder.join().unwrap();
+        stats
+            .video_frames_allocated
+            .load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    #[test]
+    fn frame_pool_bounds_allocations_of_the_capture_pipeline() {
+        ffmpeg_next::init().unwrap();
+        const CAPTURES: usize = 2000;
+        const QUEUE_CAPACITY: usize = 4;
+
+        // Without recycling every capture allocates a frame
+        assert_eq!(
+            capture_pipeline_allocations(CAPTURES, QUEUE_CAPACITY, 0),
+            CAPTURES as u64
+        );
+        // The pool only allocates the frames in flight at once: the queued ones, the one
+        // being captured and the one being encoded
+        let pooled = capture_pipeline_allocations(CAPTURES, QUEUE_CAPACITY, QUEUE_CAPACITY + 2);
+        assert!(
+            pooled <= QUEUE_CAPACITY as u64 + 2,
+            "{} frames allocated",
+            pooled
+        );
     }
 }
@@ -44,22 +44,23 @@ impl<ControlMessageType> Worker<ControlMessageType>
 where
     ControlMessageType: From<WorkerControlMessage> + Send + 'static,
 {
-    pub fn new<MessageType, FnWorker>(message_sender: Sender<MessageType>, f: FnWorker) -> Self
+    pub fn new<MessageSenderType, FnWorker>(message_sender: MessageSenderType, f: FnWorker) -> Self
     where
-        MessageType: Send + 'static,
-        FnWorker: FnOnce(Sender<MessageType>, Receiver<ControlMessageType>) -> () + Send + 'static,
+        MessageSenderType: Send + 'static,
+        FnWorker: FnOnce(MessageSenderType, Receiver<ControlMessageType>) -> () + Send + 'static,
     {
         Self::new_with_capacity(message_sender, f, 100)
     }
 
-    pub fn new_with_capacity<MessageType, FnWorker>(
-        message_sender: Sender<MessageType>,
+    /// `message_sender` is handed to the worker thread, usually the `Sender` of a channel
+    pub fn new_with_capacity<MessageSenderType, FnWorker>(
+        message_sender: MessageSenderType,
         f: FnWorker,
         cap: usize,
     ) -> Self
     where
-        MessageType: Send + 'static,
-        FnWorker: FnOnce(Sender<MessageType>, Receiver<ControlMessageType>) -> () + Send + 'static,
+        MessageSenderType: Send + 'static,
+        FnWorker: FnOnce(MessageSenderType, Receiver<ControlMessageType>) -> () + Send + 'static,
     {
         let (control_sender, control_receiver) = crossbeam::channel::bounded(cap);
         let worker_handle = std::thread::spawn(move || {
@@ -83,6 +84,19 @@ where
         FnWorker: FnOnce(Receiver<ControlMessageType>) -> () + Send + 'static,
     {
         let (control_sender, control_receiver) = crossbeam::channel::bounded(cap);
+        Self::new_consumer_with_channel(control_sender, control_receiver, f)
+    }
+
+    /// Start a consumer on a channel that was created up front, which lets producers
+    /// queue messages before the consumer exists.
+    pub fn new_consumer_with_channel<FnWorker>(
+        control_sender: Sender<ControlMessageType>,
+        control_receiver: Receiver<ControlMessageType>,
+        f: FnWorker,
+    ) -> Self
+    where
+        FnWorker: FnOnce(Receiver<ControlMessageType>) -> () + Send + 'static,
+    {
         let worker_handle = std::thread::spawn(move || {
             f(control_receiver);
         });
@@ -105,6 +119,15 @@ where
         Ok(self.worker_handle.join()?)
     }
 
+    /// Wait for a worker that was already told to quit through its control sender
+    pub fn join(self) -> Result<(), WorkerError> {
+        Ok(self.worker_handle.join()?)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.worker_handle.is_finished()
+    }
+
     pub fn control_sender(&self) -> Sender<ControlMessageType> {
         self.control_sender.clone()
     }
@@ -0,0 +1,98 @@
+#!/usr/bin/env python3
+"""Writes box_font.ttf, a TrueType font whose printable ASCII glyphs are filled boxes.
+
+The overlay tests draw text with it, so they do not depend on the fonts installed on the
+machine running them. Run it from this directory to regenerate the font.
+"""
+
+import struct
+
+UNITS_PER_EM = 1000
+ADVANCE = 500
+# Box of every printable character except the space, in font units
+BOX = (50, 0, 450, 700)
+FIRST_CHAR, LAST_CHAR = 0x20, 0x7E
+
+
+def checksum(data):
+    data += b"\0" * (-len(data) % 4)
+    return sum(struct.unpack(">%dI" % (len(data) // 4), data)) & 0xFFFFFFFF
+
+
+def box_glyph():
+    x_min, y_min, x_max, y_max = BOX
+    # One clockwise contour of on-curve points, coordinates are deltas
+    points = [(x_min, y_min), (x_min, y_max), (x_max, y_max), (x_max, y_min)]
+    deltas, previous = [], (0, 0)
+    for point in points:
+        deltas.append((point[0] - previous[0], point[1] - previous[1]))
+        previous = point
+    data = struct.pack(">hhhhh", 1, x_min, y_min, x_max, y_max)
+    data += struct.pack(">HH", len(points) - 1, 0)
+    data += bytes([0x01] * len(points))
+    data += b"".join(struct.pack(">h", dx) for dx, _ in deltas)
+    data += b"".join(struct.pack(">h", dy) for _, dy in deltas)
+    return data + b"\0" * (-len(data) % 4)
+
+
+def main():
+    # .notdef and the space have no outline
+    glyphs = [b"", b""] + [box_glyph()] * (LAST_CHAR - FIRST_CHAR)
+    glyph_count = len(glyphs)
+
+    offsets, glyf = [0], b""
+    for glyph in glyphs:
+        glyf += glyph
+        offsets.append(len(glyf))
+    loca = struct.pack(">%dI" % len(offsets), *offsets)
+
+    x_min, y_min, x_max, y_max = BOX
+    head = struct.pack(
+        ">IIIIHHqqhhhhHHhhh",
+        0x00010000, 0x00010000, 0, 0x5F0F3CF5, 0, UNITS_PER_EM, 0, 0,
+        x_min, y_min, x_max, y_max, 0, 8, 2, 1, 0,
+    )
+    hhea = struct.pack(
+        ">IhhhHhhhhhhhhhhhH",
+        0x00010000, 800, -200, 0, ADVANCE, 0, 0, x_max, 1, 0, 0, 0, 0, 0, 0, 0,
+        glyph_count,
+    )
+    maxp = struct.pack(">IHHHHHHHHHHHHHH", 0x00010000, glyph_count, 4, 1, 0, 0, 2,
+                       0, 0, 0, 0, 0, 0, 0, 0)
+    hmtx = struct.pack(">HhHh", ADVANCE, 0, ADVANCE, 0)
+    hmtx += struct.pack(">Hh", ADVANCE, x_min) * (glyph_count - 2)
+
+    # Format 4 with one segment for the printable characters and the final 0xFFFF one
+    segments = [(FIRST_CHAR, LAST_CHAR, 1 - FIRST_CHAR), (0xFFFF, 0xFFFF, 1)]
+    count = len(segments)
+    subtable = struct.pack(">HHHHHHH", 4, 16 + 8 * count, 0, count * 2, 4, 1, 0)
+    subtable += b"".join(struct.pack(">H", end) for _, end, _ in segments)
+    subtable += struct.pack(">H", 0)
+    subtable += b"".join(struct.pack(">H", start) for start, _, _ in segments)
+    subtable += b"".join(struct.pack(">h", delta) for _, _, delta in segments)
+    subtable += struct.pack(">%dH" % count, *([0] * count))
+    cmap = struct.pack(">HHHHI", 0, 1, 3, 1, 12) + subtable
+
+    post = struct.pack(">IIhhIIIII", 0x00030000, 0, -100, 50, 0, 0, 0, 0, 0)
+
+    tables = {
+        b"cmap": cmap, b"glyf": glyf, b"head": head, b"hhea": hhea,
+        b"hmtx": hmtx, b"loca": loca, b"maxp": maxp, b"post": post,
+    }
+    count = len(tables)
+    search_range = 16 * (1 << (count.bit_length() - 1))
+    font = struct.pack(">IHHHH", 0x00010000, count, search_range,
+                       count.bit_length() - 1, count * 16 - search_range)
+    offset = len(font) + 16 * count
+    directory, data = b"", b""
+    for tag in sorted(tables):
+        table = tables[tag]
+        directory += struct.pack(">4sIII", tag, checksum(table), offset + len(data), len(table))
+        data += table + b"\0" * (-len(table) % 4)
+
+    with open("box_font.ttf", "wb") as file:
+        file.write(font + directory + data)
+
+
+if __name__ == "__main__":
+    main()